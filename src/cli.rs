@@ -0,0 +1,336 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+
+use crate::files;
+use crate::version::{self, VersionFraming, VersionOrder};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Args {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+
+  /// Directory to read resources from
+  #[arg(long, global = true, value_name = "DIR", default_value = "resources")]
+  pub input: PathBuf,
+
+  /// Directory to write generated resources to
+  #[arg(long, global = true, value_name = "DIR", default_value = "out")]
+  pub output: PathBuf,
+
+  /// File to store input file mtimes in between runs, defaults to `mtimes` in the output directory
+  #[arg(long, global = true, value_name = "FILE")]
+  pub mtimes_file: Option<PathBuf>,
+
+  /// How changed input files are detected: `mtime` compares modification times,
+  /// `hash` compares CRC32 hashes of file contents stored in the mtimes file
+  #[arg(long, global = true, value_enum, default_value_t = CacheMode::Mtime)]
+  pub cache_mode: CacheMode,
+
+  /// Only log errors and hide the progress bar, ignored if `RUST_LOG` is set
+  #[arg(short, long, global = true, conflicts_with = "verbose")]
+  pub quiet: bool,
+
+  /// Log more: `-v` for info, `-vv` for debug, `-vvv` for trace. Warnings and errors are logged by default,
+  /// ignored if `RUST_LOG` is set
+  #[arg(short, long, global = true, action = ArgAction::Count)]
+  pub verbose: u8,
+
+  /// Write the JSON Schema of `resource.yaml` to the file and exit, for editor validation
+  #[arg(long, global = true, value_name = "FILE")]
+  pub emit_schema: Option<PathBuf>,
+
+  /// Lowercase the file and texture names proplibs and maps reference, and the entry names of `library.tara`,
+  /// so lookups match on case-sensitive hosting
+  #[arg(long, global = true)]
+  pub normalize_case: bool,
+
+  /// Exit with an error if any validation warning was emitted
+  #[arg(long, global = true)]
+  pub deny_warnings: bool,
+
+  /// Number of resources to scan concurrently, defaults to the number of CPUs.
+  /// Scans wait on file metadata and hashing, so more jobs help on network filesystems
+  #[arg(long, global = true, default_value_t = default_scan_jobs())]
+  pub scan_jobs: usize,
+
+  /// Maximum number of files read or written concurrently
+  #[arg(long, global = true, default_value_t = files::DEFAULT_MAX_OPEN_FILES)]
+  pub max_open_files: usize,
+
+  /// Format of the resource list: `json` writes `00-resources.json` at the end,
+  /// `ndjson` streams one resource per line to `00-resources.ndjson` as they are generated
+  #[arg(long, global = true, value_enum, default_value_t = ManifestFormat::Json)]
+  pub manifest_format: ManifestFormat,
+
+  /// Format of the log: `json` writes one JSON object per event, with the fields of the resource being generated
+  #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+  pub log_format: LogFormat,
+
+  /// Write `generated.json` listing regenerated resources and the input files that triggered them
+  #[arg(long, global = true)]
+  pub generated_json: bool,
+
+  /// Write `timings.csv` with the time each regenerated resource took to write its outputs, slowest first
+  #[arg(long, global = true)]
+  pub timings: bool,
+
+  /// Write `size-report.json` with the number of resources and output bytes of each kind
+  #[arg(long, global = true)]
+  pub size_report: bool,
+
+  /// Re-encode JPEG images of resources that don't set `normalize_jpeg` themselves
+  #[arg(long, global = true)]
+  pub normalize_jpeg: bool,
+
+  /// Comma-separated namespace keys whose values prefix the output path of each resource,
+  /// e.g. `theme` writes `theme=winter` resources to `out/winter/<encoded>`
+  #[arg(long, global = true, value_delimiter = ',')]
+  pub output_namespace_layout: Vec<String>,
+
+  /// Layout of the output files: `octal` id/version directories, or `content` which also stores
+  /// every output file once under `content/<prefix>/<sha256>` and lists the digests in `manifest.json`
+  #[arg(long, global = true, value_enum, default_value_t = OutputLayout::Octal)]
+  pub output_layout: OutputLayout,
+
+  /// Comma-separated namespace keys every resource under a namespaced directory must have
+  #[arg(long, global = true, value_delimiter = ',')]
+  pub require_namespace: Vec<String>,
+
+  /// Fail instead of warning about proplibs without `gen` and `theme` namespaces, which maps can't validate
+  #[arg(long, global = true)]
+  pub strict_namespaces: bool,
+
+  /// Write content hashes of all input files, keyed by path relative to the resources root,
+  /// to a file that CI can keep between runs
+  #[arg(long, global = true, value_name = "FILE")]
+  pub export_cache: Option<PathBuf>,
+
+  /// Detect changed input files by comparing content hashes from a file written by `--export-cache`
+  /// instead of mtimes, which don't survive a fresh checkout
+  #[arg(long, global = true, value_name = "FILE")]
+  pub import_cache: Option<PathBuf>,
+
+  /// Generate only the resources named in a file, one per line, and the resources they depend on
+  #[arg(long, global = true, value_name = "FILE")]
+  pub build_list: Option<PathBuf>,
+
+  /// Generate only the resources whose name matches a glob pattern, e.g. `maps.*`,
+  /// and the resources they depend on. Can be repeated
+  #[arg(long, global = true, value_name = "PATTERN")]
+  pub only: Vec<String>,
+
+  /// Generate only the resources in a namespace, e.g. `theme=summer`, and the resources
+  /// they depend on. Can be repeated or comma separated, resources must match every one
+  #[arg(long, global = true, value_name = "KEY=VALUE", value_delimiter = ',', value_parser = parse_namespace)]
+  pub namespace: Vec<(String, String)>,
+
+  /// Skip the resources whose name matches a glob pattern, in addition to the patterns
+  /// listed in `.resourceignore` at the input root. Takes precedence over `--only`. Can be repeated
+  #[arg(long, global = true, value_name = "PATTERN")]
+  pub exclude: Vec<String>,
+
+  /// Warn about diffuse images that aren't 8-bit RGB or RGBA and alpha images that aren't grayscale
+  #[arg(long, global = true)]
+  pub validate_color_depth: bool,
+
+  /// Decode every image input and fail if one is corrupt, always enabled with `--strict`
+  #[arg(long, global = true)]
+  pub validate_images: bool,
+
+  /// Check that every proplib mesh has a default texture map and that its file exists,
+  /// even if no map places the prop
+  #[arg(long, global = true)]
+  pub validate_meshes: bool,
+
+  /// Write `unused-props.txt` listing proplib props that no map places
+  #[arg(long, global = true)]
+  pub report_unused_props: bool,
+
+  /// Write uncompressed localization files for resources that don't set `compressed` themselves
+  #[arg(long, global = true)]
+  pub uncompressed_localization: bool,
+
+  /// Regenerate all localization resources, even if their input files have not changed
+  #[arg(long, global = true)]
+  pub force_localization: bool,
+
+  /// Decode every written localization file again and log its contents
+  #[arg(long, global = true)]
+  pub verify_localization: bool,
+
+  /// Only check that textures referenced by map props exist, without reading their image headers
+  #[arg(long, global = true)]
+  pub skip_texture_probe: bool,
+
+  /// Ship SwfLibrary and Sound sources that don't start with an SWF signature
+  #[arg(long, global = true)]
+  pub skip_swf_signature_check: bool,
+
+  /// Fail if a resource doesn't output exactly the files listed in its `expected_outputs`
+  #[arg(long, global = true)]
+  pub verify: bool,
+
+  /// Radix of the proplib ids and versions written to `proplibs.xml` of maps. The original client parses hex,
+  /// changing it changes the generated `proplibs.xml`
+  #[arg(long, global = true, value_enum, default_value_t = Radix::Hex)]
+  pub proplibs_radix: Radix,
+
+  /// Compress `.tara` archives of proplibs, multiframe textures and sound banks.
+  /// Versions don't depend on it, so clear the output directory after changing it
+  #[arg(long, global = true, value_enum, default_value_t = ArchiveCompression::None)]
+  pub compress: ArchiveCompression,
+
+  /// Print the name, kind, id and namespaces of every discovered resource and exit without generating anything
+  #[arg(long, global = true)]
+  pub list: bool,
+
+  /// Print why each regenerated resource is considered changed, and the input files that triggered it
+  #[arg(long, global = true)]
+  pub explain: bool,
+
+  /// Validate and print the resources that would be regenerated and, with `--prune`, the directories
+  /// that would be removed, without writing anything. Fails if any changes are pending
+  #[arg(long, global = true)]
+  pub dry_run: bool,
+
+  /// Write `meta.json` with the kind and kind-specific details (image dimensions, sound duration,
+  /// map statistics) next to the outputs of each regenerated resource
+  #[arg(long, global = true)]
+  pub meta: bool,
+
+  /// Fail on the first missing prop, mesh or texture instead of reporting all of them at the end
+  #[arg(long, global = true)]
+  pub strict: bool,
+
+  /// Warn instead of failing when two resources have the same id
+  #[arg(long, global = true)]
+  pub allow_id_collisions: bool,
+
+  /// Remove output directories of deleted resources and old versions after generation
+  #[arg(long, global = true)]
+  pub prune: bool,
+
+  /// Previous `00-resources.json` or `00-resources.ndjson` to compare against when writing a patch
+  #[arg(long, global = true, value_name = "FILE", requires = "patch_out")]
+  pub patch_against: Option<PathBuf>,
+
+  /// Directory to copy the outputs of resources that are new or changed since `--patch-against` to
+  #[arg(long, global = true, value_name = "DIR", requires = "patch_against")]
+  pub patch_out: Option<PathBuf>,
+
+  /// Order input files are fed to the version digest in
+  #[arg(long, global = true, value_enum, default_value_t = VersionOrder::Path)]
+  pub version_order: VersionOrder,
+
+  /// Lengths fed to the version digest in addition to file contents
+  #[arg(long, global = true, value_enum, default_value_t = VersionFraming::None)]
+  pub version_framing: VersionFraming,
+
+  /// Initial CRC value of the version digest, decimal or `0x`-prefixed hex (default 0xffffffff)
+  #[arg(long, global = true, value_parser = version::parse_seed)]
+  pub version_seed: Option<u32>,
+}
+
+static ARGS: OnceLock<Args> = OnceLock::new();
+
+fn default_scan_jobs() -> usize {
+  std::thread::available_parallelism().map_or(1, usize::from)
+}
+
+fn parse_namespace(value: &str) -> Result<(String, String), String> {
+  match value.split_once('=') {
+    Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+    _ => Err(format!("expected KEY=VALUE, got {:?}", value)),
+  }
+}
+
+/// Parses the command line arguments, must be called once at startup before [`args`].
+pub fn init() -> &'static Args {
+  ARGS.get_or_init(Args::parse)
+}
+
+pub fn args() -> &'static Args {
+  ARGS.get().expect("command line arguments are not parsed")
+}
+
+/// Initializes the default arguments for tests of code that reads [`args`].
+#[cfg(test)]
+pub fn init_defaults() -> &'static Args {
+  ARGS.get_or_init(|| Args::parse_from(["resource-generator"]))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CacheMode {
+  Mtime,
+  Hash,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+  Json,
+  Ndjson,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputLayout {
+  Octal,
+  Content,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+  Text,
+  Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveCompression {
+  None,
+  Gzip,
+  Zstd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Radix {
+  Hex,
+  Decimal,
+}
+
+impl Radix {
+  pub fn format(self, value: i64) -> String {
+    match self {
+      Radix::Hex => format!("{:x}", value),
+      Radix::Decimal => value.to_string(),
+    }
+  }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Validate the resource tree, or the resources selected by `--only`, and report all problems,
+  /// without writing anything
+  Check,
+}
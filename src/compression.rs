@@ -0,0 +1,52 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Write;
+
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use tracing::debug;
+
+use crate::cli::{self, ArchiveCompression};
+
+/// zstd level 0 selects the library default.
+const ZSTD_LEVEL: i32 = 0;
+
+/// Compresses a written archive with `--compress`, the client or server must decompress it before parsing.
+pub fn compress_archive(name: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+  let compression = cli::args().compress;
+  let compressed = match compression {
+    ArchiveCompression::None => return Ok(data),
+    ArchiveCompression::Gzip => {
+      let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+      encoder.write_all(&data)?;
+      encoder.finish()?
+    }
+    ArchiveCompression::Zstd => zstd::encode_all(data.as_slice(), ZSTD_LEVEL)?,
+  };
+
+  debug!(
+    "compressed {} with {:?}: {} -> {} bytes ({:.1}%)",
+    name,
+    compression,
+    data.len(),
+    compressed.len(),
+    compressed.len() as f64 / data.len().max(1) as f64 * 100.0
+  );
+  Ok(compressed)
+}
@@ -0,0 +1,116 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use tracing::{error, warn};
+
+use crate::cli;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub message: String,
+}
+
+static DIAGNOSTICS: Mutex<Vec<Diagnostic>> = Mutex::new(Vec::new());
+
+/// Logs a content validation problem and records it, so the build can fail
+/// at the end instead of on the first problem.
+pub fn report(severity: Severity, message: String) {
+  match severity {
+    Severity::Warning => warn!("{}", message),
+    Severity::Error => error!("{}", message),
+  }
+  DIAGNOSTICS.lock().unwrap().push(Diagnostic { severity, message });
+}
+
+pub fn warning(message: impl Into<String>) {
+  report(Severity::Warning, message.into());
+}
+
+pub fn error(message: impl Into<String>) {
+  report(Severity::Error, message.into());
+}
+
+pub fn count(severity: Severity) -> usize {
+  DIAGNOSTICS
+    .lock()
+    .unwrap()
+    .iter()
+    .filter(|diagnostic| diagnostic.severity == severity)
+    .count()
+}
+
+/// A missing or broken part of a resource found during validation.
+#[derive(Clone, Debug)]
+pub struct ValidationError {
+  pub resource: String,
+  /// `library/group/prop` path of the prop the problem was found in
+  pub prop: Option<String>,
+  pub message: String,
+  pub file: Option<PathBuf>,
+}
+
+impl Display for ValidationError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.resource)?;
+    if let Some(prop) = &self.prop {
+      write!(f, ": prop {}", prop)?;
+    }
+    write!(f, ": {}", self.message)
+  }
+}
+
+/// Collects validation errors so all of them can be reported at once.
+/// With `--strict`, the first error fails immediately instead.
+#[derive(Debug, Default)]
+pub struct ValidationErrors {
+  errors: Vec<ValidationError>,
+}
+
+impl ValidationErrors {
+  pub fn push(&mut self, error: ValidationError) -> Result<()> {
+    if cli::args().strict {
+      bail!("{}", error);
+    }
+    self.errors.push(error);
+    Ok(())
+  }
+
+  pub fn extend(&mut self, errors: Vec<ValidationError>) {
+    self.errors.extend(errors);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.errors.is_empty()
+  }
+
+  pub fn into_vec(self) -> Vec<ValidationError> {
+    self.errors
+  }
+}
@@ -0,0 +1,100 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+pub const DEFAULT_MAX_OPEN_FILES: usize = 256;
+
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Semaphore with a permit per open file, and the number of permits it was created with.
+static OPEN_FILES: OnceLock<(Semaphore, usize)> = OnceLock::new();
+
+/// Sets the maximum number of files read or written concurrently.
+/// Has no effect once any file has been opened.
+pub fn set_max_open_files(limit: usize) {
+  let limit = limit.max(1);
+  let _ = OPEN_FILES.set((Semaphore::new(limit), limit));
+}
+
+async fn acquire() -> SemaphorePermit<'static> {
+  acquire_many(1).await
+}
+
+/// Acquires a permit for each of `files` held open at once.
+/// Never more than the limit, so a limit of 1 still lets a copy run.
+async fn acquire_many(files: usize) -> SemaphorePermit<'static> {
+  let (semaphore, limit) = OPEN_FILES.get_or_init(|| (Semaphore::new(DEFAULT_MAX_OPEN_FILES), DEFAULT_MAX_OPEN_FILES));
+  semaphore
+    .acquire_many(files.min(*limit) as u32)
+    .await
+    .expect("open files semaphore is never closed")
+}
+
+pub async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+  let _permit = acquire().await;
+  fs::read(path).await
+}
+
+/// Reads a file in chunks without loading all of it into memory, returning its length.
+pub async fn read_chunks(path: impl AsRef<Path>, mut chunk: impl FnMut(&[u8])) -> io::Result<u64> {
+  let _permit = acquire().await;
+  let mut file = fs::File::open(path).await?;
+  let mut buffer = vec![0; CHUNK_SIZE];
+  let mut length = 0;
+  loop {
+    let read = file.read(&mut buffer).await?;
+    if read == 0 {
+      return Ok(length);
+    }
+    chunk(&buffer[..read]);
+    length += read as u64;
+  }
+}
+
+/// Reads at most `length` bytes from the start of a file.
+pub async fn read_header(path: impl AsRef<Path>, length: usize) -> io::Result<Vec<u8>> {
+  let _permit = acquire().await;
+  let file = fs::File::open(path).await?;
+  let mut header = Vec::with_capacity(length);
+  file.take(length as u64).read_to_end(&mut header).await?;
+  Ok(header)
+}
+
+pub async fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+  let _permit = acquire().await;
+  fs::read_to_string(path).await
+}
+
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+  let _permit = acquire().await;
+  fs::write(path, contents).await
+}
+
+/// Copies a file without loading all of it into memory, returning its length.
+pub async fn copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<u64> {
+  // Holds the source and the destination open at once
+  let _permit = acquire_many(2).await;
+  fs::copy(from, to).await
+}
@@ -0,0 +1,198 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::Cursor;
+
+use anyhow::{bail, Result};
+use image::codecs::jpeg::{JpegDecoder, JpegEncoder};
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, ExtendedColorType, ImageDecoder, ImageFormat, ImageOutputFormat};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Quality used when re-encoding JPEG images. Re-encoding is lossy, so normalizing
+/// an already compressed image loses a bit of detail once, in exchange for stable output.
+pub const JPEG_QUALITY: u8 = 90;
+
+/// Decodes a JPEG image and encodes it again with a fixed encoder and quality, so images
+/// with identical pixels produce identical bytes regardless of the tool that exported them.
+/// Data in other formats is returned unchanged.
+pub fn normalize_jpeg(data: Vec<u8>) -> Result<Vec<u8>> {
+  if image::guess_format(&data).ok() != Some(ImageFormat::Jpeg) {
+    return Ok(data);
+  }
+
+  let image = image::load_from_memory_with_format(&data, ImageFormat::Jpeg)?;
+  encode_jpeg(&image)
+}
+
+pub fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>> {
+  let mut data = Vec::new();
+  {
+    let mut encoder = JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY);
+    match image {
+      DynamicImage::ImageLuma8(image) => encoder.encode_image(image)?,
+      image => encoder.encode_image(&image.to_rgb8())?,
+    }
+  }
+  Ok(data)
+}
+
+/// Format a texture is shipped in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFormat {
+  Jpeg,
+  Png,
+  /// Lossless WebP.
+  Webp,
+}
+
+impl TextureFormat {
+  pub fn image_format(self) -> ImageFormat {
+    match self {
+      TextureFormat::Jpeg => ImageFormat::Jpeg,
+      TextureFormat::Png => ImageFormat::Png,
+      TextureFormat::Webp => ImageFormat::WebP,
+    }
+  }
+}
+
+/// Transcodes an image to `format`. Data already in that format is returned unchanged.
+/// The flag is set if the image was converted.
+pub fn convert(data: Vec<u8>, format: TextureFormat) -> Result<(Vec<u8>, bool)> {
+  if image::guess_format(&data)? == format.image_format() {
+    return Ok((data, false));
+  }
+
+  Ok((encode(&image::load_from_memory(&data)?, format)?, true))
+}
+
+pub fn encode(image: &DynamicImage, format: TextureFormat) -> Result<Vec<u8>> {
+  match format {
+    TextureFormat::Jpeg => encode_jpeg(image),
+    TextureFormat::Png => {
+      let mut data = Cursor::new(Vec::new());
+      image.write_to(&mut data, ImageOutputFormat::Png)?;
+      Ok(data.into_inner())
+    }
+    TextureFormat::Webp => {
+      let image = image.to_rgba8();
+      let mut data = Vec::new();
+      WebPEncoder::new_lossless(&mut data).encode(image.as_raw(), image.width(), image.height(), ColorType::Rgba8)?;
+      Ok(data)
+    }
+  }
+}
+
+/// Downscales an image to fit within `max_width`x`max_height` keeping its aspect ratio and format.
+/// Images that already fit are returned unchanged. The flag is set if the image was resized.
+pub fn fit(data: Vec<u8>, [max_width, max_height]: [u32; 2]) -> Result<(Vec<u8>, bool)> {
+  if max_width == 0 || max_height == 0 {
+    bail!("max_size {}x{} is empty", max_width, max_height);
+  }
+
+  let (format, width, height) = probe(&data)?;
+  if width <= max_width && height <= max_height {
+    return Ok((data, false));
+  }
+
+  let format = match format {
+    ImageFormat::Jpeg => TextureFormat::Jpeg,
+    ImageFormat::Png => TextureFormat::Png,
+    ImageFormat::WebP => TextureFormat::Webp,
+    format => bail!("unsupported image format {:?}", format),
+  };
+  let image = image::load_from_memory(&data)?.resize(max_width, max_height, FilterType::Lanczos3);
+  Ok((encode(&image, format)?, true))
+}
+
+/// What an image is used for, which determines the pixel format the engine expects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageRole {
+  /// 8-bit RGB or RGBA.
+  Diffuse,
+  /// Grayscale.
+  Alpha,
+}
+
+/// Returns grayscale images unchanged, and converts color images to luminance encoded as PNG.
+/// The flag is set if the image was converted.
+pub fn to_mask(data: Vec<u8>) -> Result<(Vec<u8>, bool)> {
+  if check_color_type(&data, ImageRole::Alpha).is_ok() {
+    return Ok((data, false));
+  }
+
+  let mask = image::load_from_memory(&data)?.to_luma8();
+  let mut data = Cursor::new(Vec::new());
+  mask.write_to(&mut data, ImageOutputFormat::Png)?;
+  Ok((data.into_inner(), true))
+}
+
+/// Reads the color type an image is stored with, without decoding its pixels.
+pub fn color_type(data: &[u8]) -> Result<ExtendedColorType> {
+  Ok(match image::guess_format(data)? {
+    ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(data))?.original_color_type(),
+    ImageFormat::Png => PngDecoder::new(Cursor::new(data))?.original_color_type(),
+    format => bail!("unsupported image format {:?}", format),
+  })
+}
+
+/// Fails if the image isn't stored in the format the engine expects for `role`.
+/// 16-bit and CMYK images are decoded fine by most tools, but render wrong in the client.
+pub fn check_color_type(data: &[u8], role: ImageRole) -> Result<()> {
+  let color_type = color_type(data)?;
+  let valid = match role {
+    ImageRole::Diffuse => matches!(color_type, ExtendedColorType::Rgb8 | ExtendedColorType::Rgba8),
+    ImageRole::Alpha => matches!(
+      color_type,
+      ExtendedColorType::L1 | ExtendedColorType::L2 | ExtendedColorType::L4 | ExtendedColorType::L8
+    ),
+  };
+  if !valid {
+    let expected = match role {
+      ImageRole::Diffuse => "8-bit RGB or RGBA",
+      ImageRole::Alpha => "grayscale of at most 8 bits",
+    };
+    bail!("image is {:?}, expected {}", color_type, expected);
+  }
+
+  Ok(())
+}
+
+/// Decodes an image to check it is not truncated or stored in another format than its extension says.
+pub fn verify(data: &[u8]) -> Result<()> {
+  let reader = image::io::Reader::new(Cursor::new(data)).with_guessed_format()?;
+  if reader.format().is_none() {
+    bail!("unrecognized image format");
+  }
+  reader.decode()?;
+  Ok(())
+}
+
+/// Reads the header of an image, failing if the data isn't an image in a supported format.
+pub fn probe(data: &[u8]) -> Result<(ImageFormat, u32, u32)> {
+  let reader = image::io::Reader::new(Cursor::new(data)).with_guessed_format()?;
+  let Some(format) = reader.format() else {
+    bail!("unrecognized image format");
+  };
+  let (width, height) = reader.into_dimensions()?;
+  Ok((format, width, height))
+}
@@ -24,15 +24,20 @@ mod map;
 mod multiframe_texture;
 mod object3d;
 mod proplib;
+mod raw_data;
 mod sound;
+mod sound_bank;
 mod swf_library;
 mod texture;
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize, Serializer};
 
 pub use self::game_object::*;
@@ -43,9 +48,13 @@ pub use self::map::*;
 pub use self::multiframe_texture::*;
 pub use self::object3d::*;
 pub use self::proplib::*;
+pub use self::raw_data::*;
 pub use self::sound::*;
+pub use self::sound_bank::*;
 pub use self::swf_library::*;
 pub use self::texture::*;
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::{files, imaging, is_path_hidden};
 
 #[derive(Clone, Debug, Serialize)]
 pub struct ResourceInfo {
@@ -53,22 +62,147 @@ pub struct ResourceInfo {
   pub id: i64,
   pub version: i64,
   pub namespaces: HashMap<String, String>,
+  /// Output directory relative to the output root, see [`ResourceInfo::output_path`].
+  pub path: String,
+  #[serde(skip)]
+  pub properties: ResourceProperties,
 }
 
+/// Properties shared by all resource kinds, read from the same `resource.yaml` as the kind-specific fields.
+/// Short definitions always use the defaults.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct ResourceProperties {
+  /// Glob patterns, relative to the resource root, of input files excluded from the version digest.
+  /// Matching files are still shipped if the kind outputs them, but changing them doesn't bump the version.
+  /// `*` doesn't match `/`, use `**` to match across directories.
+  #[serde(default)]
+  pub version_ignore: Vec<String>,
+  /// Names of resources that must be generated before this one. Their outputs are passed
+  /// to [`Resource::init_dependencies`], and regenerating any of them regenerates this resource.
+  #[serde(default)]
+  pub depends_on: Vec<String>,
+  /// Names of the files the resource must output, checked with `--verify`.
+  #[serde(default)]
+  pub expected_outputs: Option<Vec<String>>,
+  /// Overrides the id, which is otherwise the CRC32 of the resource path.
+  /// For keeping ids of resources migrated from another numbering scheme.
+  #[serde(default)]
+  pub id: Option<u32>,
+}
+
+/// Output of a resource listed in `depends_on`.
+#[derive(Clone, Debug)]
+pub struct DependencyOutput {
+  pub name: String,
+  pub id: i64,
+  pub version: i64,
+  /// Directory the output files were written to.
+  pub path: PathBuf,
+  pub files: Vec<String>,
+}
+
+impl DependencyOutput {
+  pub fn new(info: &ResourceInfo, files: Vec<String>, path: PathBuf) -> Self {
+    Self {
+      name: info.name.clone(),
+      id: info.id,
+      version: info.version,
+      path,
+      files,
+    }
+  }
+}
+
+#[cfg(test)]
 impl ResourceInfo {
-  pub fn encode(&self) -> String {
-    format!(
+  /// Info of a resource at the root of the output directory, for tests.
+  pub fn for_test(name: &str, id: i64, version: i64) -> Self {
+    Self {
+      name: name.to_owned(),
+      id,
+      version,
+      namespaces: HashMap::new(),
+      path: String::new(),
+      properties: Default::default(),
+    }
+  }
+}
+
+impl ResourceInfo {
+  /// Returns the octal `id high/id mid/id byte/id byte/version` path of the resource.
+  /// The segments hold id bits 32..63, 16..31, 8..15 and 0..7, so they don't overlap and every id
+  /// in `0..=i64::MAX` has its own path; ids derived from paths only use the lower three segments.
+  /// Negative ids and versions outside `0..=u32::MAX` are an error instead of a malformed path.
+  pub fn encode(&self) -> Result<String> {
+    if self.id < 0 {
+      bail!("resource {} has negative id {}", self.name, self.id);
+    }
+    if !(0..=u32::MAX as i64).contains(&self.version) {
+      bail!(
+        "resource {} has version {} outside of 0..={}",
+        self.name,
+        self.version,
+        u32::MAX
+      );
+    }
+
+    let path = format!(
       "{:o}/{:o}/{:o}/{:o}/{:o}",
       (self.id >> 32) & 0xffffffff,
       (self.id >> 16) & 0xffff,
       (self.id >> 8) & 0xff,
       self.id & 0xff,
       self.version
-    )
+    );
+    debug_assert_eq!(Self::decode(&path), Some((self.id, self.version)));
+    Ok(path)
+  }
+
+  /// Parses a path written by [`ResourceInfo::encode`] back into the id and version.
+  /// Returns `None` for anything [`ResourceInfo::encode`] can't produce.
+  pub fn decode(path: &str) -> Option<(i64, i64)> {
+    let segments = path
+      .split('/')
+      .map(|segment| {
+        // `from_str_radix` also accepts a sign
+        let digits = !segment.is_empty() && segment.bytes().all(|byte| matches!(byte, b'0'..=b'7'));
+        digits.then(|| i64::from_str_radix(segment, 8).ok()).flatten()
+      })
+      .collect::<Option<Vec<_>>>()?;
+    let [high, mid, byte, low, version] = segments[..] else {
+      return None;
+    };
+    if !(0..=0x7fffffff).contains(&high)
+      || !(0..=0xffff).contains(&mid)
+      || !(0..=0xff).contains(&byte)
+      || !(0..=0xff).contains(&low)
+      || !(0..=u32::MAX as i64).contains(&version)
+    {
+      return None;
+    }
+
+    Some(((high << 32) | (mid << 16) | (byte << 8) | low, version))
+  }
+
+  /// Returns [`ResourceInfo::encode`] prefixed with the values of the `layout` namespace keys, in order.
+  /// Resources missing any of the keys use the flat encoded path.
+  pub fn output_path(&self, layout: &[String]) -> Result<String> {
+    let mut path = String::new();
+    for key in layout {
+      match self.namespaces.get(key) {
+        Some(value) => {
+          path.push_str(value);
+          path.push('/');
+        }
+        None => return self.encode(),
+      }
+    }
+    path.push_str(&self.encode()?);
+    Ok(path)
   }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type")]
 pub enum ResourceDefinition {
   SwfLibrary(SwfLibraryResource),
@@ -82,9 +216,27 @@ pub enum ResourceDefinition {
   LocalizedImage(LocalizedImageResource),
   Object3D(Object3DResource),
   // Effects (unused)
-  // RawData (unused)
+  RawData(RawDataResource),
   Localization(LocalizationResource),
   GameObject(GameObjectResource),
+  SoundBank(SoundBankResource),
+}
+
+/// Layout of a `resource.yaml`, which is read both as a [ResourceDefinition] and as [ResourceProperties].
+/// Only used to generate its JSON Schema.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+#[schemars(rename = "ResourceDefinition")]
+struct ResourceFile {
+  #[serde(flatten)]
+  definition: ResourceDefinition,
+  #[serde(flatten)]
+  properties: ResourceProperties,
+}
+
+/// Returns the JSON Schema of `resource.yaml` for `--emit-schema`, with a variant per `type`.
+pub fn definition_schema() -> RootSchema {
+  schemars::schema_for!(ResourceFile)
 }
 
 impl ResourceDefinition {
@@ -101,6 +253,63 @@ impl ResourceDefinition {
       ResourceDefinition::Object3D(resource) => resource,
       ResourceDefinition::Localization(resource) => resource,
       ResourceDefinition::GameObject(resource) => resource,
+      ResourceDefinition::SoundBank(resource) => resource,
+      ResourceDefinition::RawData(resource) => resource,
+    }
+  }
+
+  /// Values of `type` accepted in `resource.yaml`.
+  pub const KIND_NAMES: &'static [&'static str] = &[
+    "SwfLibrary",
+    "Sound",
+    "Map",
+    "Proplib",
+    "Texture",
+    "Image",
+    "MultiframeTexture",
+    "LocalizedImage",
+    "Object3D",
+    "RawData",
+    "Localization",
+    "GameObject",
+    "SoundBank",
+  ];
+
+  /// Returns the `type` the resource is declared with.
+  pub fn kind_name(&self) -> &'static str {
+    match self {
+      ResourceDefinition::SwfLibrary(_) => "SwfLibrary",
+      ResourceDefinition::Sound(_) => "Sound",
+      ResourceDefinition::Map(_) => "Map",
+      ResourceDefinition::Proplib(_) => "Proplib",
+      ResourceDefinition::Texture(_) => "Texture",
+      ResourceDefinition::Image(_) => "Image",
+      ResourceDefinition::MultiframeTexture(_) => "MultiframeTexture",
+      ResourceDefinition::LocalizedImage(_) => "LocalizedImage",
+      ResourceDefinition::Object3D(_) => "Object3D",
+      ResourceDefinition::Localization(_) => "Localization",
+      ResourceDefinition::GameObject(_) => "GameObject",
+      ResourceDefinition::SoundBank(_) => "SoundBank",
+      ResourceDefinition::RawData(_) => "RawData",
+    }
+  }
+
+  /// Returns the client resource type, `None` for kinds only used by the server.
+  pub fn kind(&self) -> Option<ResourceKind> {
+    match self {
+      ResourceDefinition::SwfLibrary(_) => Some(ResourceKind::SwfLibrary),
+      ResourceDefinition::Sound(_) => Some(ResourceKind::Sound),
+      ResourceDefinition::Map(_) => Some(ResourceKind::Map),
+      ResourceDefinition::Proplib(_) => Some(ResourceKind::Proplib),
+      ResourceDefinition::Texture(_) => Some(ResourceKind::Texture),
+      ResourceDefinition::Image(_) => Some(ResourceKind::Image),
+      ResourceDefinition::MultiframeTexture(_) => Some(ResourceKind::MultiframeTexture),
+      ResourceDefinition::LocalizedImage(_) => Some(ResourceKind::LocalizedImage),
+      ResourceDefinition::Object3D(_) => Some(ResourceKind::Object3D),
+      ResourceDefinition::RawData(_) => Some(ResourceKind::RawData),
+      ResourceDefinition::Localization(_) | ResourceDefinition::GameObject(_) | ResourceDefinition::SoundBank(_) => {
+        None
+      }
     }
   }
 
@@ -117,6 +326,135 @@ impl ResourceDefinition {
       ResourceDefinition::Object3D(resource) => resource,
       ResourceDefinition::Localization(resource) => resource,
       ResourceDefinition::GameObject(resource) => resource,
+      ResourceDefinition::SoundBank(resource) => resource,
+      ResourceDefinition::RawData(resource) => resource,
+    }
+  }
+}
+
+/// Contents of `meta.json`, written next to the outputs of each resource with `--meta`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ResourceMetadata {
+  pub kind: &'static str,
+  #[serde(flatten)]
+  pub details: Option<MetadataDetails>,
+}
+
+/// Kind-specific metadata, so the server doesn't need to parse the main asset.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum MetadataDetails {
+  Image(ImageMetadata),
+  MultiframeTexture(MultiframeTextureProperties),
+  Sound(SoundMetadata),
+  Map(MapMetadata),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageMetadata {
+  pub width: u32,
+  pub height: u32,
+}
+
+impl ImageMetadata {
+  pub async fn probe(file: &Path) -> Result<Self> {
+    let data = files::read(file).await?;
+    Self::from_data(&data).with_context(|| format!("failed to read image {}", file.display()))
+  }
+
+  pub fn from_data(data: &[u8]) -> Result<Self> {
+    let (_, width, height) = imaging::probe(data)?;
+    Ok(Self { width, height })
+  }
+}
+
+/// Checks the entries of an images manifest, given as `(name, diffuse, alpha)`: names must be unique
+/// ignoring case, as textures are looked up case-insensitively, and file names must not be empty.
+pub fn validate_image_manifest<'a>(
+  resource: &str,
+  images: impl IntoIterator<Item = (&'a str, &'a str, Option<&'a str>)>,
+  errors: &mut ValidationErrors,
+) -> Result<()> {
+  let error = |message: String| ValidationError {
+    resource: resource.to_owned(),
+    prop: None,
+    message,
+    file: None,
+  };
+  let mut names = HashMap::new();
+  for (name, diffuse, alpha) in images {
+    if let Some(previous) = names.insert(name.to_lowercase(), name) {
+      errors.push(error(format!(
+        "image {} is defined more than once (also as {})",
+        name, previous
+      )))?;
+    }
+    if diffuse.is_empty() {
+      errors.push(error(format!("image {} has no diffuse file", name)))?;
+    }
+    if alpha.is_some_and(str::is_empty) {
+      errors.push(error(format!("image {} has an empty alpha file name", name)))?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Whether a directory walk entry below the walk root is hidden, like the paths the resource scan skips.
+/// Hidden directories are pruned with their contents by [walkdir::FilterEntry].
+pub fn is_hidden_entry(entry: &walkdir::DirEntry) -> bool {
+  entry.depth() > 0 && is_path_hidden(entry.file_name())
+}
+
+/// Reads a file, naming it in the error.
+pub async fn read_file(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+  let path = path.as_ref();
+  files::read(path)
+    .await
+    .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Reads a file as UTF-8, naming it in the error.
+pub async fn read_file_to_string(path: impl AsRef<Path>) -> Result<String> {
+  let path = path.as_ref();
+  files::read_to_string(path)
+    .await
+    .with_context(|| format!("failed to read {}", path.display()))
+}
+
+/// Writes `files` to `dir`, the default [`Resource::write_outputs`].
+pub async fn write_output_files(dir: &Path, files: HashMap<String, Vec<u8>>) -> Result<Vec<String>> {
+  let mut written = Vec::new();
+  for (name, data) in files {
+    files::write(dir.join(&name), data)
+      .await
+      .with_context(|| format!("failed to write {}", dir.join(&name).display()))?;
+    written.push(name);
+  }
+  Ok(written)
+}
+
+/// Copies `source` to `dir` as `name`, for kinds that ship a source file unchanged.
+pub async fn copy_output_file(source: &Path, dir: &Path, name: &str) -> Result<Vec<String>> {
+  files::copy(source, dir.join(name))
+    .await
+    .with_context(|| format!("failed to copy {} to {}", source.display(), dir.join(name).display()))?;
+  Ok(vec![name.to_owned()])
+}
+
+/// Adds a file to the outputs of `resource`, failing instead of silently overwriting
+/// a file with the same name added by another producer.
+pub fn insert_output_file(
+  files: &mut HashMap<String, Vec<u8>>,
+  resource: &ResourceInfo,
+  name: String,
+  data: Vec<u8>,
+) -> Result<()> {
+  match files.entry(name) {
+    Entry::Occupied(entry) => bail!("resource {} outputs {} more than once", resource.name, entry.key()),
+    Entry::Vacant(entry) => {
+      entry.insert(data);
+      Ok(())
     }
   }
 }
@@ -129,8 +467,34 @@ pub trait Resource {
   fn get_root(&self) -> PathBuf;
   fn get_info(&self) -> &Option<ResourceInfo>;
 
+  /// Called before [`Resource::output_files`] with the outputs of the resources listed in `depends_on`.
+  fn init_dependencies(&mut self, _dependencies: Vec<DependencyOutput>) {}
+
   async fn input_files(&self) -> Result<Vec<PathBuf>>;
+
+  /// Returns the data used instead of the contents of `file` to calculate the version,
+  /// for resources that transform their inputs before writing them out.
+  async fn version_data(&self, _file: &Path) -> Result<Option<Vec<u8>>> {
+    Ok(None)
+  }
+
+  /// Checks the resource before [`Resource::output_files`] is called, returning all problems found.
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    Ok(Vec::new())
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>>;
+
+  /// Writes the outputs to `dir` and returns their names. Kinds that can ship a source file unchanged
+  /// override it to copy the file instead of holding it in memory.
+  async fn write_outputs(&self, dir: &Path) -> Result<Vec<String>> {
+    write_output_files(dir, self.output_files().await?).await
+  }
+
+  /// Returns the kind-specific part of `meta.json`, called after [`Resource::output_files`].
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    Ok(None)
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -147,7 +511,7 @@ pub enum ResourceKind {
   LocalizedImage,
   Object3D,
   Effects, // Unused
-  RawData, // Unused
+  RawData,
 }
 
 impl From<&ResourceKind> for i32 {
@@ -176,3 +540,63 @@ impl Serialize for ResourceKind {
     serializer.serialize_i32(i32::from(self))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn encode_round_trips() {
+    for (id, version) in [(0, 0), (1, 1), (0x12345678, 42), (0x1_0000_0000, 7)] {
+      let path = ResourceInfo::for_test("test", id, version).encode().unwrap();
+      assert_eq!(ResourceInfo::decode(&path), Some((id, version)), "{}", path);
+    }
+  }
+
+  #[test]
+  fn encode_rejects_out_of_range() {
+    assert!(ResourceInfo::for_test("test", -1, 1).encode().is_err());
+    assert!(ResourceInfo::for_test("test", 1, -1).encode().is_err());
+    assert!(ResourceInfo::for_test("test", 1, u32::MAX as i64 + 1).encode().is_err());
+  }
+
+  #[test]
+  fn decode_rejects_out_of_range() {
+    assert_eq!(ResourceInfo::decode("20000000000/0/0/0/0"), None);
+    assert_eq!(ResourceInfo::decode("0/200000/0/0/0"), None);
+    assert_eq!(ResourceInfo::decode("0/0/400/0/0"), None);
+    assert_eq!(ResourceInfo::decode("0/0/0/400/0"), None);
+    assert_eq!(ResourceInfo::decode("0/0/0/0/40000000000"), None);
+  }
+
+  #[test]
+  fn decode_boundaries() {
+    let max = ResourceInfo::for_test("test", i64::MAX, u32::MAX as i64);
+    assert_eq!(max.encode().unwrap(), "17777777777/177777/377/377/37777777777");
+    assert_eq!(
+      ResourceInfo::decode("17777777777/177777/377/377/37777777777"),
+      Some((i64::MAX, u32::MAX as i64))
+    );
+    assert_eq!(ResourceInfo::decode("0/0/0/0/0"), Some((0, 0)));
+    // Segments don't overlap, so each one only sets its own bits
+    assert_eq!(ResourceInfo::decode("1/0/0/0/0"), Some((1 << 32, 0)));
+    assert_eq!(ResourceInfo::decode("0/1/0/0/0"), Some((1 << 16, 0)));
+    assert_eq!(ResourceInfo::decode("0/0/1/0/0"), Some((1 << 8, 0)));
+  }
+
+  #[test]
+  fn decode_rejects_malformed_paths() {
+    for path in [
+      "",
+      "0/0/0/0",
+      "0/0/0/0/0/0",
+      "0/0/0/0/8",
+      "0/0/0/0/-1",
+      "0/0/0/0/+1",
+      "0//0/0/0",
+      "a/0/0/0/0",
+    ] {
+      assert_eq!(ResourceInfo::decode(path), None, "{:?}", path);
+    }
+  }
+}
@@ -21,17 +21,19 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::kind::{read_file, ResourceInfo};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct GameObjectResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub file: Option<PathBuf>,
 }
@@ -62,7 +64,7 @@ impl Resource for GameObjectResource {
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
     Ok(HashMap::from([(
       "object.kdl".to_owned(),
-      fs::read(self.get_object()).await.unwrap(),
+      read_file(self.get_object()).await?,
     )]))
   }
 }
@@ -17,23 +17,32 @@
  */
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
+use tracing::debug;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::kind::{copy_output_file, read_file, write_output_files, ImageMetadata, MetadataDetails, ResourceInfo};
+use crate::{cli, imaging};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ImageResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub image: Option<PathBuf>,
+  /// Re-encode JPEG images so pixel-identical re-exports keep the same version,
+  /// defaults to `--normalize-jpeg`.
+  pub normalize_jpeg: Option<bool>,
+  /// Downscale the image to fit within `[width, height]`, keeping its aspect ratio.
+  pub max_size: Option<[u32; 2]>,
 }
 
 #[async_trait]
@@ -59,11 +68,31 @@ impl Resource for ImageResource {
     Ok(vec![self.get_image()])
   }
 
+  async fn version_data(&self, file: &Path) -> Result<Option<Vec<u8>>> {
+    if self.reencodes() && file == self.get_image() {
+      return Ok(Some(self.read_image().await?));
+    }
+    Ok(None)
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
-    Ok(HashMap::from([(
-      "image.tnk".to_owned(),
-      fs::read(self.get_image()).await.unwrap(),
-    )]))
+    Ok(HashMap::from([("image.tnk".to_owned(), self.read_image().await?)]))
+  }
+
+  async fn write_outputs(&self, dir: &Path) -> Result<Vec<String>> {
+    if self.reencodes() {
+      return write_output_files(dir, self.output_files().await?).await;
+    }
+    copy_output_file(&self.get_image(), dir, "image.tnk").await
+  }
+
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    // Resizing changes the dimensions of the output
+    let metadata = match self.max_size {
+      Some(_) => ImageMetadata::from_data(&self.read_image().await?)?,
+      None => ImageMetadata::probe(&self.get_image()).await?,
+    };
+    Ok(Some(MetadataDetails::Image(metadata)))
   }
 }
 
@@ -81,4 +110,32 @@ impl ImageResource {
       })
       .unwrap_or_else(|| self.get_root().join("image.jpg"))
   }
+
+  fn normalizes_jpeg(&self) -> bool {
+    self.normalize_jpeg.unwrap_or(cli::args().normalize_jpeg)
+  }
+
+  /// Whether the source may be encoded again instead of being shipped as is.
+  fn reencodes(&self) -> bool {
+    self.normalizes_jpeg() || self.max_size.is_some()
+  }
+
+  async fn read_image(&self) -> Result<Vec<u8>> {
+    let mut data = read_file(self.get_image()).await?;
+    if let Some(max_size) = self.max_size {
+      let (resized, changed) = imaging::fit(data, max_size)?;
+      data = resized;
+      // Resized images come from our own encoder already
+      if changed {
+        debug!("downscaled {} to fit {:?}", self.info.as_ref().unwrap().name, max_size);
+        return Ok(data);
+      }
+    }
+
+    if self.normalizes_jpeg() {
+      imaging::normalize_jpeg(data)
+    } else {
+      Ok(data)
+    }
+  }
 }
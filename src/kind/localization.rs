@@ -18,31 +18,36 @@
 
 use std::collections::HashMap;
 use std::io::Cursor;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use alternativa_protocol::protocol_buffer::{ProtocolBuffer, ProtocolBufferCompressedExt};
+use alternativa_protocol::protocol_buffer::{FinalCodec, ProtocolBuffer, ProtocolBufferCompressedExt};
 use alternativa_protocol::Codec;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 use tracing::info;
 use walkdir::WalkDir;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
-use crate::RESOURCE_DEFINITION_FILE;
+use crate::kind::{is_hidden_entry, ResourceInfo};
+use crate::{cli, file_exists_case_insensitive, files, missing_file_message, RESOURCE_DEFINITION_FILE};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct LocalizationResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
+  /// Image keys to `<directory>/<file>` paths, read from `<directory>/images/<file>` in the resource directory.
   #[serde(skip_serializing)]
   pub images: HashMap<String, PathBuf>,
   #[serde(skip_serializing)]
   pub strings: HashMap<String, String>,
+  /// Write a compressed protocol buffer, defaults to `true` unless `--uncompressed-localization` is passed.
+  pub compressed: Option<bool>,
 }
 
 #[derive(Debug, Codec)]
@@ -84,7 +89,11 @@ impl Resource for LocalizationResource {
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for entry in WalkDir::new(self.get_root()) {
+    for entry in WalkDir::new(self.get_root())
+      .sort_by_file_name()
+      .into_iter()
+      .filter_entry(|entry| !is_hidden_entry(entry))
+    {
       let entry = entry?;
       if entry.file_type().is_dir() {
         continue;
@@ -103,17 +112,26 @@ impl Resource for LocalizationResource {
 
     let mut images = Vec::new();
     for (key, value) in &self.images {
-      let file_path = value.parent().unwrap().join("images").join(value.file_name().unwrap());
-      let file_path = self.root.join(file_path);
+      let path = self.get_image(value);
+      let Some(file) = file_exists_case_insensitive(&path) else {
+        bail!(
+          "localization {}: image {}: {}",
+          self.info.as_ref().unwrap().name,
+          key,
+          missing_file_message(&path)
+        );
+      };
 
       images.push(LocalizationImage {
         key: key.clone(),
-        value: fs::read(file_path).await.unwrap(),
+        value: files::read(&file)
+          .await
+          .with_context(|| format!("failed to read {}", file.display()))?,
       });
     }
 
     let localization = Localization {
-      images: vec![],
+      images,
       strings: self
         .strings
         .iter()
@@ -128,16 +146,26 @@ impl Resource for LocalizationResource {
 
     info!("Encoded protocol buffer: {:?}", protocol_buffer.data.get_ref().len());
 
+    let compressed = self.compressed.unwrap_or(!cli::args().uncompressed_localization);
     let mut data = Cursor::new(Vec::new());
-    protocol_buffer.encode_compressed(&mut data).unwrap();
+    if compressed {
+      protocol_buffer.encode_compressed(&mut data).unwrap();
+    } else {
+      protocol_buffer.encode(&mut data).unwrap();
+    }
 
     let position = data.position();
     let mut data = data.into_inner();
     data.drain(..position as usize);
 
-    {
+    // Decoding doubles the work, so the round trip is only done when debugging the encoder
+    if cli::args().verify_localization {
       let mut data = Cursor::new(data.clone());
-      let mut protocol_buffer = ProtocolBuffer::decode_compressed(&mut data).unwrap();
+      let mut protocol_buffer = if compressed {
+        ProtocolBuffer::decode_compressed(&mut data).unwrap()
+      } else {
+        ProtocolBuffer::decode(&mut data).unwrap()
+      };
       info!("Decoded protocol buffer: {:?}", protocol_buffer.data.get_ref().len());
 
       let localization = Localization::decode(&mut protocol_buffer).unwrap();
@@ -150,3 +178,88 @@ impl Resource for LocalizationResource {
     Ok(files)
   }
 }
+
+impl LocalizationResource {
+  /// Resolves an `images` value `<directory>/<file>` to `<root>/<directory>/images/<file>`.
+  /// Values already starting with the resource directory are accepted too.
+  pub fn get_image(&self, value: &Path) -> PathBuf {
+    let value = value.strip_prefix(&self.root).unwrap_or(value);
+    let directory = value.parent().unwrap_or_else(|| Path::new(""));
+    let file = value.file_name().unwrap_or_default();
+    self.root.join(directory).join("images").join(file)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn localization(root: &Path, images: &[(&str, &str)]) -> LocalizationResource {
+    LocalizationResource {
+      root: root.to_path_buf(),
+      info: Some(ResourceInfo::for_test("localization.en", 1, 1)),
+      images: images
+        .iter()
+        .map(|(key, value)| (key.to_string(), PathBuf::from(value)))
+        .collect(),
+      strings: HashMap::from([("HELLO".to_owned(), "Hello".to_owned())]),
+      compressed: Some(false),
+    }
+  }
+
+  fn decode(data: Vec<u8>) -> Localization {
+    let mut data = Cursor::new(data);
+    let mut protocol_buffer = ProtocolBuffer::decode(&mut data).unwrap();
+    Localization::decode(&mut protocol_buffer).unwrap()
+  }
+
+  #[tokio::test]
+  async fn output_includes_images() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(root.path().join("en/images")).unwrap();
+    std::fs::write(root.path().join("en/images/logo.png"), b"fake png").unwrap();
+
+    let resource = localization(root.path(), &[("LOGO", "en/logo.png")]);
+    let mut files = resource.output_files().await.unwrap();
+    let localization = decode(files.remove("en.l18n").unwrap());
+
+    assert_eq!(localization.images.len(), 1);
+    assert_eq!(localization.images[0].key, "LOGO");
+    assert_eq!(localization.images[0].value, b"fake png");
+    assert_eq!(localization.strings.len(), 1);
+    assert_eq!(localization.strings[0].value, "Hello");
+  }
+
+  #[test]
+  fn get_image_resolves_into_images_directory() {
+    let resource = localization(Path::new("/resources/localization/en"), &[]);
+
+    // The layout is `<root>/<directory>/images/<file>`, with or without the root prefix in the value
+    assert_eq!(
+      resource.get_image(Path::new("en/logo.png")),
+      Path::new("/resources/localization/en/en/images/logo.png")
+    );
+    assert_eq!(
+      resource.get_image(Path::new("/resources/localization/en/en/logo.png")),
+      Path::new("/resources/localization/en/en/images/logo.png")
+    );
+    assert_eq!(
+      resource.get_image(Path::new("logo.png")),
+      Path::new("/resources/localization/en/images/logo.png")
+    );
+  }
+
+  #[tokio::test]
+  async fn missing_image_is_an_error() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(root.path().join("en/images")).unwrap();
+
+    let resource = localization(root.path(), &[("LOGO", "en/logo.png")]);
+    let error = resource.output_files().await.unwrap_err().to_string();
+
+    assert!(error.contains("localization localization.en: image LOGO"), "{}", error);
+    assert!(error.contains("logo.png"), "{}", error);
+  }
+}
@@ -21,19 +21,21 @@ use std::path::PathBuf;
 
 use anyhow::Result;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 use walkdir::WalkDir;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::kind::{insert_output_file, is_hidden_entry, read_file, ResourceInfo};
 use crate::RESOURCE_DEFINITION_FILE;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct LocalizedImageResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
 }
 
@@ -58,7 +60,11 @@ impl Resource for LocalizedImageResource {
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for entry in WalkDir::new(self.get_root()) {
+    for entry in WalkDir::new(self.get_root())
+      .sort_by_file_name()
+      .into_iter()
+      .filter_entry(|entry| !is_hidden_entry(entry))
+    {
       let entry = entry?;
       if entry.file_type().is_dir() {
         continue;
@@ -73,11 +79,13 @@ impl Resource for LocalizedImageResource {
   }
 
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
+    let info = self.info.as_ref().unwrap();
     let mut files = HashMap::new();
     for file in self.input_files().await? {
       let file_name = file.file_name().unwrap().to_str().unwrap().to_owned();
       let (name, _) = file_name.rsplit_once('.').unwrap_or((&file_name, ""));
-      files.insert(format!("{}.tnk", name), fs::read(file).await.unwrap());
+      // image.png and image.jpg would both become image.tnk
+      insert_output_file(&mut files, info, format!("{}.tnk", name), read_file(&file).await?)?;
     }
 
     Ok(files)
@@ -16,22 +16,28 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
-use std::io::Cursor;
-use std::path::PathBuf;
+use std::io::{self, Cursor};
+use std::iter;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use alternativa_protocol::protocol_buffer::{FinalCodec, ProtocolBuffer};
+use alternativa_protocol::Codec;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
 use proplib::Texture;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use threedee::Parser3DS;
-use tokio::fs;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, info};
 
-use super::{proplib, ProplibResource, Resource};
-use crate::kind::{ResourceDefinition, ResourceInfo};
-use crate::{file_exists_case_insensitive, get_texture_map_name};
+use super::{proplib, ProplibResource, Resource, PROPLIB_NAMESPACES};
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{read_file, MetadataDetails, ResourceDefinition, ResourceInfo};
+use crate::{
+  check_reference_case, cli, diagnostics, file_exists_case_insensitive, files, get_texture_map_name, imaging,
+  missing_file_message, parse_3ds, parse_xml,
+};
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename = "map")]
@@ -51,6 +57,15 @@ pub struct MapXml {
 }
 
 impl MapXml {
+  /// Returns a copy with the texture names of props lowercased, for `--normalize-case`.
+  fn with_lowercase_texture_names(&self) -> Self {
+    let mut map = self.clone();
+    for prop in &mut map.static_geometry.props {
+      prop.texture_name = prop.texture_name.to_lowercase();
+    }
+    map
+  }
+
   fn as_public(&self) -> PublicMap {
     PublicMap {
       static_geometry: &self.static_geometry,
@@ -146,6 +161,10 @@ pub struct BonusRegion {
 }
 
 impl BonusRegion {
+  fn contains_horizontal(&self, point: &Vector3) -> bool {
+    point.x >= self.min.x && point.x <= self.max.x && point.y >= self.min.y && point.y <= self.max.y
+  }
+
   fn as_private(&self) -> PrivateBonusRegion {
     PrivateBonusRegion {
       name: &self.name,
@@ -181,6 +200,9 @@ pub struct Prop {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CollisionGeometry {
+  /// External file the geometry is loaded from, relative to the map file. Merged by [read_map].
+  #[serde(default, rename = "@src", skip_serializing)]
+  pub src: Option<PathBuf>,
   #[serde(default, rename = "collision-plane")]
   pub planes: Vec<CollisionPlane>,
   #[serde(default, rename = "collision-box")]
@@ -189,6 +211,64 @@ pub struct CollisionGeometry {
   pub triangles: Vec<CollisionTriangle>,
 }
 
+/// Only the collision geometry source of a map, to find input files without parsing the whole map.
+#[derive(Deserialize)]
+#[serde(rename = "map")]
+struct MapCollisionSource {
+  #[serde(rename = "collision-geometry")]
+  collision_geometry: CollisionSource,
+}
+
+#[derive(Deserialize)]
+struct CollisionSource {
+  #[serde(default, rename = "@src")]
+  src: Option<PathBuf>,
+}
+
+/// Returns the external collision geometry file of a map, if it has one.
+async fn get_collision_file(file: &Path) -> Result<Option<PathBuf>> {
+  // Missing maps are reported with the other missing input files
+  if !file.exists() {
+    return Ok(None);
+  }
+  let map = files::read_to_string(file)
+    .await
+    .with_context(|| format!("failed to read map {}", file.display()))?;
+  let map: MapCollisionSource = parse_xml(&map, file)?;
+  Ok(map.collision_geometry.src.map(|src| resolve_collision_file(file, &src)))
+}
+
+fn resolve_collision_file(map_file: &Path, src: &Path) -> PathBuf {
+  map_file.parent().unwrap_or(Path::new("")).join(src)
+}
+
+/// Parses a map, merging the elements of an external collision geometry file into the inline ones.
+pub async fn read_map(file: &Path) -> Result<MapXml> {
+  let content = files::read_to_string(file)
+    .await
+    .with_context(|| format!("failed to read map {}", file.display()))?;
+  let mut map: MapXml = parse_xml(&content, file)?;
+
+  if let Some(src) = map.collision_geometry.src.take() {
+    let collision_file = resolve_collision_file(file, &src);
+    let content = files::read_to_string(&collision_file)
+      .await
+      .with_context(|| format!("failed to read collision geometry {}", collision_file.display()))?;
+    let external: CollisionGeometry = parse_xml(&content, &collision_file)?;
+    if external.src.is_some() {
+      bail!(
+        "collision geometry {} can't reference another file",
+        collision_file.display()
+      );
+    }
+    map.collision_geometry.planes.extend(external.planes);
+    map.collision_geometry.boxes.extend(external.boxes);
+    map.collision_geometry.triangles.extend(external.triangles);
+  }
+
+  Ok(map)
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CollisionPlane {
   #[serde(default, rename = "@id", skip_serializing_if = "Option::is_none")]
@@ -219,6 +299,113 @@ pub struct CollisionTriangle {
   pub rotation: Vector3,
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct MapMetadata {
+  pub props: usize,
+  pub proplibs: usize,
+  pub collision_planes: usize,
+  pub collision_boxes: usize,
+  pub collision_triangles: usize,
+  pub spawn_points: usize,
+  pub bonus_regions: usize,
+}
+
+/// Binary form of [`CollisionGeometry`] written as `collision.bin`, so the server
+/// can load physics data without parsing `map.xml`. Ids are not needed for physics and are omitted.
+#[derive(Debug, Codec)]
+struct CollisionData {
+  planes: Vec<CollisionPlaneData>,
+  boxes: Vec<CollisionBoxData>,
+  triangles: Vec<CollisionTriangleData>,
+}
+
+#[derive(Debug, Codec)]
+struct CollisionPlaneData {
+  width: f32,
+  length: f32,
+  position: Vector3Data,
+  rotation: Vector3Data,
+}
+
+#[derive(Debug, Codec)]
+struct CollisionBoxData {
+  size: Vector3Data,
+  position: Vector3Data,
+  rotation: Vector3Data,
+}
+
+#[derive(Debug, Codec)]
+struct CollisionTriangleData {
+  v0: Vector3Data,
+  v1: Vector3Data,
+  v2: Vector3Data,
+  position: Vector3Data,
+  rotation: Vector3Data,
+}
+
+#[derive(Debug, Codec)]
+struct Vector3Data {
+  x: f32,
+  y: f32,
+  z: f32,
+}
+
+impl From<&Vector3> for Vector3Data {
+  fn from(vector: &Vector3) -> Self {
+    Self {
+      x: vector.x,
+      y: vector.y,
+      z: vector.z,
+    }
+  }
+}
+
+impl CollisionGeometry {
+  fn encode_binary(&self) -> io::Result<Vec<u8>> {
+    let data = CollisionData {
+      planes: self
+        .planes
+        .iter()
+        .map(|plane| CollisionPlaneData {
+          width: plane.width,
+          length: plane.length,
+          position: (&plane.position).into(),
+          rotation: (&plane.rotation).into(),
+        })
+        .collect(),
+      boxes: self
+        .boxes
+        .iter()
+        .map(|collision_box| CollisionBoxData {
+          size: (&collision_box.size).into(),
+          position: (&collision_box.position).into(),
+          rotation: (&collision_box.rotation).into(),
+        })
+        .collect(),
+      triangles: self
+        .triangles
+        .iter()
+        .map(|triangle| CollisionTriangleData {
+          v0: (&triangle.v0).into(),
+          v1: (&triangle.v1).into(),
+          v2: (&triangle.v2).into(),
+          position: (&triangle.position).into(),
+          rotation: (&triangle.rotation).into(),
+        })
+        .collect(),
+    };
+
+    let mut buffer = ProtocolBuffer::new();
+    data.encode(&mut buffer)?;
+
+    let mut data = Cursor::new(Vec::new());
+    buffer.encode(&mut data)?;
+
+    let position = data.position() as usize;
+    Ok(data.get_ref()[position..].to_vec())
+  }
+}
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct SpawnPoints {
   #[serde(rename = "spawn-point")]
@@ -311,6 +498,22 @@ pub struct Vector3 {
   pub z: f32,
 }
 
+impl Vector3 {
+  fn horizontal_distance(&self, other: &Vector3) -> f32 {
+    ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+  }
+
+  fn is_zero(&self) -> bool {
+    self.x == 0.0 && self.y == 0.0 && self.z == 0.0
+  }
+}
+
+/// Spawn points closer than this (horizontally) to a CTF flag or DOM keypoint are reported.
+const SPAWN_PROTECTION_RADIUS: f32 = 500.0;
+
+/// DOM maps with fewer keypoints than this are reported, as the mode needs contested points between the bases.
+const MIN_DOM_KEYPOINTS: usize = 3;
+
 #[derive(Debug, Serialize)]
 #[serde(rename = "proplibs")]
 pub struct ProplibsXml {
@@ -328,18 +531,110 @@ pub struct LibraryXml {
   pub version: String,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+/// Namespace whose values are expected to have their own builds of a library.
+const THEME_NAMESPACE: &str = "theme";
+
+/// Returns a message for each library with props in `placed` that resolve to different proplib versions
+/// within one theme, e.g. when `gen=1` and `gen=2` builds of a library are mixed.
+/// Only namespace combinations that agree with the namespaces of the map are compared.
+fn find_version_conflicts(
+  namespaces: &HashMap<String, String>,
+  placed: &BTreeSet<(&str, &str, &str)>,
+  proplib_versions: &HashMap<BTreeMap<String, String>, Vec<ProplibResource>>,
+) -> Vec<String> {
+  // version -> (id, namespaces)
+  type Resolved<'a> = BTreeMap<i64, BTreeSet<(i64, &'a BTreeMap<String, String>)>>;
+
+  // (theme, library, group, prop) -> versions
+  let mut props = BTreeMap::<(Option<&str>, &str, &str, &str), Resolved>::new();
+  for (combination, resources) in proplib_versions {
+    let applies = namespaces
+      .iter()
+      .all(|(key, value)| combination.get(key).map_or(true, |other| other == value));
+    if !applies {
+      continue;
+    }
+    let theme = combination.get(THEME_NAMESPACE).map(String::as_str);
+    for resource in resources {
+      let info = resource.info.as_ref().unwrap();
+      let library = resource.library.as_ref().unwrap();
+      for group in &library.prop_groups {
+        for prop in &group.props {
+          let key = (library.name.as_str(), group.name.as_str(), prop.name.as_str());
+          if !placed.contains(&key) {
+            continue;
+          }
+          props
+            .entry((theme, key.0, key.1, key.2))
+            .or_default()
+            .entry(info.version)
+            .or_default()
+            .insert((info.id, combination));
+        }
+      }
+    }
+  }
+
+  // Reported once per library, listing the affected props
+  let mut libraries = BTreeMap::<(Option<&str>, &str), (Resolved, Vec<String>)>::new();
+  for ((theme, library, group, prop), versions) in props {
+    if versions.len() < 2 {
+      continue;
+    }
+    let (resolved, affected) = libraries.entry((theme, library)).or_default();
+    for (version, resources) in versions {
+      resolved.entry(version).or_default().extend(resources);
+    }
+    affected.push(format!("{}/{}", group, prop));
+  }
+
+  libraries
+    .into_iter()
+    .map(|((theme, library), (resolved, props))| {
+      let versions = resolved
+        .iter()
+        .map(|(version, resources)| {
+          let resources = resources
+            .iter()
+            .map(|(id, namespaces)| format!("{} {:?}", id, namespaces))
+            .collect::<Vec<_>>();
+          format!("version {} ({})", version, resources.join(", "))
+        })
+        .collect::<Vec<_>>();
+      format!(
+        "library {} resolves to {} proplib versions for theme {}: {}; used by {}",
+        library,
+        resolved.len(),
+        theme.unwrap_or("(none)"),
+        versions.join("; "),
+        props.join(", ")
+      )
+    })
+    .collect()
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MapResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   #[serde(skip)]
   pub parsed: Option<MapXml>,
   #[serde(skip)]
+  pub variants: BTreeMap<String, MapXml>,
+  #[serde(skip)]
   pub proplibs: HashMap<String, ResourceDefinition>,
+  /// Proplibs referenced by the map, grouped by namespaces, to validate props against every combination.
+  #[serde(skip)]
+  pub proplib_versions: HashMap<BTreeMap<String, String>, Vec<ProplibResource>>,
 
   pub map: Option<PathBuf>,
+  /// Variants of the map sharing its collision geometry, by variant name.
+  #[serde(default)]
+  pub maps: BTreeMap<String, PathBuf>,
   pub namespace: Option<String>,
 }
 
@@ -358,6 +653,7 @@ impl Debug for MapResource {
       )
       .field("proplibs", &self.proplibs)
       .field("map", &self.map)
+      .field("maps", &self.maps)
       .field("namespace", &self.namespace)
       .finish()
   }
@@ -370,6 +666,18 @@ impl Resource for MapResource {
   }
 
   async fn init(&mut self, info: ResourceInfo) -> Result<()> {
+    for variant in self.maps.keys() {
+      if variant.is_empty()
+        || !variant
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+      {
+        bail!(
+          "map variant {:?} must only contain letters, digits, '-' and '_'",
+          variant
+        );
+      }
+    }
     self.info = Some(info);
     Ok(())
   }
@@ -383,332 +691,702 @@ impl Resource for MapResource {
   }
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
-    Ok(vec![self.get_map()])
+    let mut files = Vec::new();
+    for file in self.get_map_files() {
+      let collision_file = get_collision_file(&file).await?;
+      files.push(file);
+      // Variants usually share the collision geometry file
+      if let Some(collision_file) = collision_file {
+        if !files.contains(&collision_file) {
+          files.push(collision_file);
+        }
+      }
+    }
+    Ok(files)
   }
 
-  async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
-    let proplibs = ProplibsXml {
-      libraries: self
-        .proplibs
-        .iter()
-        .map(|(name, definition)| {
-          let info = definition.resource().get_info().as_ref().unwrap();
-          LibraryXml {
-            name: name.clone(),
-            id: format!("{:x}", info.id),
-            version: format!("{:x}", info.version),
+  /// Checks that every prop placed on the map exists in the proplibs of each namespace combination,
+  /// and that its mesh and textures exist. Problems are collected instead of stopping at the first one.
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    info!("validating props for {:?}", self.get_info());
+    let name = self.get_info().as_ref().unwrap().name.clone();
+    let mut errors = ValidationErrors::default();
+    let normalize_case = cli::args().normalize_case;
+    self.validate_gameplay(&mut errors)?;
+    self.validate_proplib_versions();
+
+    for (namespaces, resources) in &self.proplib_versions {
+      info!("checking proplibs {:?}: {:?} proplibs", namespaces, resources.len());
+      // Each of the proplibs was already reported when scanned, or failed it with --strict-namespaces
+      if !PROPLIB_NAMESPACES.iter().all(|key| namespaces.contains_key(*key)) {
+        debug!("skipping malformed proplibs combination {:?}", namespaces);
+        continue;
+      }
+
+      // build index
+      let mut props =
+        HashMap::<(String, String, String), (&ProplibResource, &proplib::PropGroup, proplib::Prop)>::default();
+      for resource in resources {
+        let library = resource.library.as_ref().unwrap();
+        for group in &library.prop_groups {
+          for prop in &group.props {
+            props.insert(
+              (library.name.to_owned(), group.name.to_owned(), prop.name.to_owned()),
+              (resource, group, prop.clone()),
+            );
           }
-        })
-        .collect(),
-    };
+        }
+      }
+
+      // TODO: Actually this should be shared for all maps,
+      // there is no reason to check same props for each map again.
+      // library, group, prop, texture
+      let mut checked = Vec::<(String, String, String, String)>::new();
+
+      // library, group, prop
+      let mut unresolved = BTreeSet::<(String, String, String)>::new();
+
+      for map_prop in self.get_parsed().flat_map(|(_, map)| &map.static_geometry.props) {
+        let Some((proplib, group, prop)) = props.get(&(
+          map_prop.library_name.clone(),
+          map_prop.group_name.clone(),
+          map_prop.name.clone(),
+        )) else {
+          unresolved.insert((
+            map_prop.library_name.clone(),
+            map_prop.group_name.clone(),
+            map_prop.name.clone(),
+          ));
+          continue;
+        };
+        if checked.contains(&(
+          map_prop.library_name.clone(),
+          map_prop.group_name.clone(),
+          map_prop.name.clone(),
+          map_prop.texture_name.clone(),
+        )) {
+          continue;
+        }
+        checked.push((
+          map_prop.library_name.clone(),
+          map_prop.group_name.clone(),
+          map_prop.name.clone(),
+          map_prop.texture_name.clone(),
+        ));
+
+        let root = proplib.get_root();
+        let library = proplib.library.as_ref().unwrap();
+        let prop_path = format!("{}/{}/{}", library.name, group.name, prop.name);
+        let error = |message: String, file: Option<PathBuf>| ValidationError {
+          resource: name.clone(),
+          prop: Some(prop_path.clone()),
+          message,
+          file,
+        };
+
+        // Texture files to check, as (kind, texture name, file)
+        let mut textures = Vec::new();
+        if let Some(mesh) = &prop.mesh {
+          let mesh_path = root.join(&mesh.file);
+          let mesh_file = file_exists_case_insensitive(&mesh_path);
+          if let Some(mesh_file) = &mesh_file {
+            check_reference_case(&format!("prop {}", prop_path), &mesh_path, mesh_file);
+          }
+
+          let (texture_name, texture) = if !map_prop.texture_name.is_empty() {
+            (
+              map_prop.texture_name.to_owned(),
+              mesh
+                .textures
+                .iter()
+                .find(|texture| {
+                  texture.name == map_prop.texture_name
+                    || (normalize_case && texture.name.to_lowercase() == map_prop.texture_name.to_lowercase())
+                })
+                .cloned(),
+            )
+          } else if let Some(mesh_file) = &mesh_file {
+            let data = read_file(mesh_file).await?;
+            let main = match parse_3ds(&data, mesh_file) {
+              Ok(main) => main,
+              Err(parse_error) => {
+                errors.push(error(format!("{:#}", parse_error), Some(mesh_file.clone())))?;
+                continue;
+              }
+            };
+            let Some(default_texture) = get_texture_map_name(&main) else {
+              errors.push(error(
+                format!("mesh {} has no default texture map", mesh_file.display()),
+                None,
+              ))?;
+              continue;
+            };
+            (
+              default_texture.to_owned(),
+              Some(Texture {
+                name: default_texture.to_owned(),
+                diffuse_map: default_texture.to_owned(),
+              }),
+            )
+          } else {
+            errors.push(error(
+              format!("mesh file {}", missing_file_message(&mesh_path)),
+              Some(mesh_path),
+            ))?;
+            continue;
+          };
+
+          let Some(texture) = &texture else {
+            errors.push(error(format!("texture {} not exists", texture_name), None))?;
+            continue;
+          };
+          if let Some(images) = &proplib.images {
+            let image = images
+              .images
+              .iter()
+              .find(|image| image.name.to_lowercase() == texture.diffuse_map.to_lowercase());
+            let Some(image) = image else {
+              errors.push(error(format!("texture mapping for {:?} not exists", texture), None))?;
+              continue;
+            };
+            textures.push((
+              "diffuse file for texture",
+              image.name.clone(),
+              root.join(&image.diffuse),
+            ));
+            if let Some(alpha) = &image.alpha {
+              textures.push(("alpha file for texture", image.name.clone(), root.join(alpha)));
+            }
+          } else {
+            textures.push((
+              "diffuse file for texture",
+              texture_name,
+              root.join(&texture.diffuse_map),
+            ));
+          }
+        } else if let Some(sprite) = &prop.sprite {
+          if let Some(images) = &proplib.images {
+            let image = images
+              .images
+              .iter()
+              .find(|image| image.name.to_lowercase() == sprite.file.to_lowercase());
+            let Some(image) = image else {
+              errors.push(error(
+                format!("texture mapping for sprite {:?} not exists", sprite),
+                None,
+              ))?;
+              continue;
+            };
+            textures.push(("diffuse file for sprite", image.name.clone(), root.join(&image.diffuse)));
+            if let Some(alpha) = &image.alpha {
+              textures.push(("alpha file for sprite", image.name.clone(), root.join(alpha)));
+            }
+          } else {
+            textures.push(("sprite file", sprite.file.clone(), root.join(&sprite.file)));
+          }
+        } else {
+          errors.push(error("has neither mesh nor sprite".to_owned(), None))?;
+          continue;
+        }
+
+        for (kind, texture_name, file) in textures {
+          match file_exists_case_insensitive(&file) {
+            Some(found) => {
+              check_reference_case(&format!("prop {} {}", prop_path, kind), &file, &found);
+              probe_texture(&found, &library.name, &group.name, &prop.name, &texture_name).await
+            }
+            None => errors.push(error(
+              format!("{} {}: {}", kind, texture_name, missing_file_message(&file)),
+              Some(file),
+            ))?,
+          }
+        }
+      }
 
+      if !unresolved.is_empty() {
+        let unresolved = unresolved
+          .iter()
+          .map(|(library, group, prop)| format!("{}/{}/{}", library, group, prop))
+          .collect::<Vec<_>>();
+        errors.push(ValidationError {
+          resource: name.clone(),
+          prop: None,
+          message: format!(
+            "{} props not found in proplibs {:?}: {}",
+            unresolved.len(),
+            namespaces,
+            unresolved.join(", ")
+          ),
+          file: None,
+        })?;
+      }
+    }
+
+    Ok(errors.into_vec())
+  }
+
+  async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
+    let radix = cli::args().proplibs_radix;
     let parsed = self.parsed.as_ref().unwrap();
-    info!("static geometry: {} props", parsed.static_geometry.props.len());
     info!(
       "collision geometry: {} boxes, {} planes, {} triangles",
       parsed.collision_geometry.boxes.len(),
       parsed.collision_geometry.planes.len(),
       parsed.collision_geometry.triangles.len()
     );
-    Ok(HashMap::from([
-      (
-        "map.xml".to_owned(),
-        quick_xml::se::to_string(&parsed.as_public())?.into_bytes(),
-      ),
-      (
-        "proplibs.xml".to_owned(),
-        quick_xml::se::to_string(&proplibs)?.into_bytes(),
-      ),
-      (
-        "private.json".to_owned(),
-        serde_json::to_vec_pretty(&parsed.as_private(&self.proplibs))?,
-      ),
-    ]))
+    let mut files = HashMap::from([("collision.bin".to_owned(), parsed.collision_geometry.encode_binary()?)]);
+
+    for (variant, map) in self.get_parsed() {
+      let suffix = variant.map(|variant| format!("-{}", variant)).unwrap_or_default();
+      info!("static geometry{}: {} props", suffix, map.static_geometry.props.len());
+
+      // Variants may place props from other libraries than the default map
+      let proplibs = self.get_used_proplibs(map);
+      let proplibs_xml = ProplibsXml {
+        libraries: proplibs
+          .iter()
+          .map(|(name, definition)| {
+            let info = definition.resource().get_info().as_ref().unwrap();
+            LibraryXml {
+              name: name.clone(),
+              id: radix.format(info.id),
+              version: radix.format(info.version),
+            }
+          })
+          .collect(),
+      };
+
+      let normalized;
+      let public = if cli::args().normalize_case {
+        normalized = map.with_lowercase_texture_names();
+        normalized.as_public()
+      } else {
+        map.as_public()
+      };
+      files.insert(
+        format!("map{}.xml", suffix),
+        quick_xml::se::to_string(&public)?.into_bytes(),
+      );
+      files.insert(
+        format!("proplibs{}.xml", suffix),
+        quick_xml::se::to_string(&proplibs_xml)?.into_bytes(),
+      );
+      files.insert(
+        format!("private{}.json", suffix),
+        serde_json::to_vec_pretty(&map.as_private(&proplibs))?,
+      );
+    }
+
+    Ok(files)
+  }
+
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    let parsed = self.parsed.as_ref().unwrap();
+    Ok(Some(MetadataDetails::Map(MapMetadata {
+      props: parsed.static_geometry.props.len(),
+      proplibs: self.proplibs.len(),
+      collision_planes: parsed.collision_geometry.planes.len(),
+      collision_boxes: parsed.collision_geometry.boxes.len(),
+      collision_triangles: parsed.collision_geometry.triangles.len(),
+      spawn_points: parsed.spawn_points.spawn_points.len(),
+      bonus_regions: parsed.bonus_regions.bonus_regions.len(),
+    })))
   }
 }
 
 impl MapResource {
+  fn resolve(&self, file: &Path) -> PathBuf {
+    if file.starts_with(&self.root) {
+      file.to_owned()
+    } else {
+      self.get_root().join(file)
+    }
+  }
+
   pub fn get_map(&self) -> PathBuf {
     self
       .map
-      .clone()
-      .map(|file| {
-        if file.starts_with(&self.root) {
-          file
-        } else {
-          self.get_root().join(file)
-        }
-      })
+      .as_deref()
+      .map(|file| self.resolve(file))
       .unwrap_or_else(|| self.get_root().join("map.xml"))
   }
 
-  pub async fn init_proplibs(&mut self, resources: &[ResourceDefinition]) -> Result<()> {
-    let map = self.get_map();
-    let map = fs::read_to_string(map).await.unwrap();
-    let map: MapXml = quick_xml::de::from_str(&map)?;
+  /// Returns the default map file followed by the files of the variants.
+  pub fn get_map_files(&self) -> Vec<PathBuf> {
+    iter::once(self.get_map())
+      .chain(self.maps.values().map(|file| self.resolve(file)))
+      .collect()
+  }
+
+  /// Returns the default map and its variants by variant name, once parsed by [MapResource::init_proplibs].
+  pub fn get_parsed(&self) -> impl Iterator<Item = (Option<&str>, &MapXml)> {
+    self
+      .parsed
+      .iter()
+      .map(|map| (None, map))
+      .chain(self.variants.iter().map(|(variant, map)| (Some(variant.as_str()), map)))
+  }
 
-    let proplib_names: HashSet<_> = map
+  /// Returns the resolved proplibs that props of `map` are placed from.
+  fn get_used_proplibs(&self, map: &MapXml) -> HashMap<String, ResourceDefinition> {
+    let names: HashSet<_> = map
       .static_geometry
       .props
       .iter()
       .map(|prop| &prop.library_name)
       .collect();
+    self
+      .proplibs
+      .iter()
+      .filter(|(name, _)| names.contains(name))
+      .map(|(name, definition)| (name.clone(), definition.clone()))
+      .collect()
+  }
+
+  pub async fn init_proplibs(&mut self, resources: &[ResourceDefinition]) -> Result<()> {
+    let map = read_map(&self.get_map()).await?;
+
+    let mut variants = BTreeMap::new();
+    for (variant, file) in &self.maps {
+      let map = read_map(&self.resolve(file))
+        .await
+        .with_context(|| format!("failed to read map variant {}", variant))?;
+      variants.insert(variant.clone(), map);
+    }
+
+    let proplib_names: HashSet<_> = iter::once(&map)
+      .chain(variants.values())
+      .flat_map(|map| &map.static_geometry.props)
+      .map(|prop| &prop.library_name)
+      .collect();
+    self.proplib_versions.clear();
     for definition in resources {
       if let ResourceDefinition::Proplib(resource) = definition {
+        let namespaces = &resource.get_info().as_ref().unwrap().namespaces;
+        let version = self
+          .proplib_versions
+          .entry(namespaces.iter().map(|(k, v)| (k.to_owned(), v.to_owned())).collect())
+          .or_default();
         let name = resource.name.as_ref().unwrap();
         if proplib_names.contains(name) {
           debug!("resolved proplib {}", name);
           self.proplibs.insert(name.clone(), definition.clone());
+          version.push(resource.clone());
         }
       }
     }
 
     for name in proplib_names {
       if !self.proplibs.contains_key(name) {
-        warn!("proplib {} not found for namespace {:?}", name, self.namespace);
+        diagnostics::warning(format!("proplib {} not found for namespace {:?}", name, self.namespace));
       }
     }
     self.parsed = Some(map);
+    self.variants = variants;
 
     Ok(())
   }
 
-  pub async fn validate_props(&mut self, resources: &[ResourceDefinition]) -> Result<()> {
-    info!("validating props for {:?}", self.get_info());
-
-    let mut versions: HashMap<BTreeMap<String, String>, Vec<&ProplibResource>> = HashMap::new();
-    for definition in resources {
-      if let ResourceDefinition::Proplib(resource) = definition {
-        let namespaces = &resource.get_info().as_ref().unwrap().namespaces;
-        versions
-          .entry(namespaces.iter().map(|(k, v)| (k.to_owned(), v.to_owned())).collect())
-          .or_default()
-          .push(resource);
-      }
+  fn get_variant_name(&self, variant: Option<&str>) -> String {
+    let name = &self.get_info().as_ref().unwrap().name;
+    match variant {
+      Some(variant) => format!("{} ({})", name, variant),
+      None => name.clone(),
     }
+  }
 
-    for (namespaces, resources) in &versions {
-      info!("checking proplibs {:?}: {:?} proplibs", namespaces, resources.len());
-      if !namespaces.contains_key("gen") || !namespaces.contains_key("theme") {
-        warn!("malformed proplibs combination: {:?}", namespaces);
-        continue;
-      }
+  /// Checks that the map is playable in the modes it has elements for: it has a spawn point,
+  /// both CTF flags are placed and DOM has enough keypoints. Problems are warnings unless `--strict` is set.
+  fn validate_gameplay(&self, errors: &mut ValidationErrors) -> Result<()> {
+    for (variant, map) in self.get_parsed() {
+      let name = self.get_variant_name(variant);
 
-      // build index
-      let mut props =
-        HashMap::<(String, String, String), (&ProplibResource, &proplib::PropGroup, proplib::Prop)>::default();
-      for resource in resources {
-        let library = resource.library.as_ref().unwrap();
-        for group in &library.prop_groups {
-          for prop in &group.props {
-            props.insert(
-              (library.name.to_owned(), group.name.to_owned(), prop.name.to_owned()),
-              (resource, group, prop.clone()),
-            );
+      let mut problems = Vec::new();
+      if map.spawn_points.spawn_points.is_empty() {
+        problems.push("has no spawn points".to_owned());
+      }
+      if let Some(flags) = &map.ctf_flags {
+        for (team, position) in [("blue", &flags.blue), ("red", &flags.red)] {
+          if position.is_zero() {
+            problems.push(format!("{} flag is not placed", team));
           }
         }
       }
+      if let Some(keypoints) = &map.dom_keypoints {
+        if keypoints.dom_keypoints.len() < MIN_DOM_KEYPOINTS {
+          problems.push(format!(
+            "has {} DOM keypoints, at least {} are required",
+            keypoints.dom_keypoints.len(),
+            MIN_DOM_KEYPOINTS
+          ));
+        }
+      }
 
-      // TODO: Actually this should be shared for all maps,
-      // there is no reason to check same props for each map again.
-      // library, group, prop, texture
-      let mut checked = Vec::<(String, String, String, String)>::new();
+      for problem in problems {
+        if cli::args().strict {
+          errors.push(ValidationError {
+            resource: name.clone(),
+            prop: None,
+            message: problem,
+            file: None,
+          })?;
+        } else {
+          diagnostics::warning(format!("map {}: {}", name, problem));
+        }
+      }
+    }
 
-      let map = self.parsed.as_ref().unwrap();
-      'prop: for map_prop in &map.static_geometry.props {
-        if let Some((proplib, group, prop)) = props.get(&(
-          map_prop.library_name.clone(),
-          map_prop.group_name.clone(),
-          map_prop.name.clone(),
-        )) {
-          if checked.contains(&(
-            map_prop.library_name.clone(),
-            map_prop.group_name.clone(),
-            map_prop.name.clone(),
-            map_prop.texture_name.clone(),
-          )) {
-            continue;
-          }
+    Ok(())
+  }
 
-          // info!("found prop {:?} in {:?}", map_prop, prop);
-          let root = proplib.get_root();
-          let library = proplib.library.as_ref().unwrap();
-          if let Some(mesh) = &prop.mesh {
-            let mesh_file = root.join(&mesh.file);
-            let mesh_file = file_exists_case_insensitive(&mesh_file);
-
-            // info!("texture-name: {:?}, prop: {:?}", map_prop.texture_name, prop.name);
-            let (texture_name, texture) = if !map_prop.texture_name.is_empty() {
-              (
-                map_prop.texture_name.to_owned(),
-                mesh
-                  .textures
-                  .iter()
-                  .find(|texture| texture.name == map_prop.texture_name)
-                  .cloned(),
-              )
-            } else if let Some(mesh_file) = &mesh_file {
-              let data = fs::read(mesh_file).await.unwrap();
-              let mut data = Cursor::new(data.as_slice());
-              let mut parser = Parser3DS::new(&mut data);
-              let main = &parser.read_main()[0];
-              let default_texture = get_texture_map_name(main);
-              if let Some(default_texture) = &default_texture {
-                (
-                  default_texture.to_owned(),
-                  Some(Texture {
-                    name: default_texture.to_owned(),
-                    diffuse_map: default_texture.to_owned(),
-                  }),
-                )
-
-                // let default_file = file_exists_case_insensitive(root.join(default_texture));
-                // if let Some(default_file) = &default_file {
-                //   // info!("{:?}", default_file);
-                //   (default_texture.to_owned(), Some(Texture {
-                //     name: default_texture.to_owned(),
-                //     diffuse_map: default_texture.to_string_lossy().into_owned()
-                //   }))
-                // } else {
-                //   (default_texture.to_owned(), None)
-                //   // panic!("mesh {}/{}/{} ({:?}) default texture {} not exists", library.name, group.name, prop.name, mesh_file, default_texture);
-                // }
-              } else {
-                panic!(
-                  "mesh {}/{}/{} ({:?}) has no default texture map",
-                  library.name, group.name, prop.name, mesh_file
-                );
-              }
-            } else {
-              panic!(
-                "mesh {}/{}/{} file {:?} not exists",
-                library.name, group.name, prop.name, mesh_file
-              );
-            };
-            // info!("texture {}: {:?}", texture_name, texture);
-
-            if let Some(texture) = &texture {
-              if let Some(images) = &proplib.images {
-                let image = images
-                  .images
-                  .iter()
-                  .find(|image| image.name.to_lowercase() == texture.diffuse_map.to_lowercase());
-                // info!("texture_file: {:?}", image);
-                if let Some(image) = image {
-                  // info!("{:?}", image);
-
-                  let file = root.join(&image.diffuse);
-                  let file = file_exists_case_insensitive(&file);
-                  if let Some(_file) = &file {
-                  } else {
-                    panic!("diffuse file {:?} for texture {} not exists", file, image.name);
-                  }
-
-                  if let Some(alpha) = &image.alpha {
-                    let file = root.join(alpha);
-                    let file = file_exists_case_insensitive(&file);
-                    if let Some(_file) = &file {
-                    } else {
-                      panic!("alpha file {:?} for texture {} not exists", file, image.name);
-                    }
-                  }
-                } else {
-                  error!("images: {:?}", images);
-                  panic!(
-                    "texture mapping for {:?} not exists for prop {}/{}/{}",
-                    texture, library.name, group.name, prop.name
-                  );
-                }
-              } else {
-                // info!("texture_file: {:?}", texture.diffuse_map);
-                let file = root.join(&texture.diffuse_map);
-                let file = file_exists_case_insensitive(&file);
-                if let Some(_file) = &file {
-                } else {
-                  error!("prop: {:?}", map_prop);
-                  error!("texture: {:?}", texture);
-                  panic!("diffuse file {:?} for texture {} not exists", file, texture_name);
-                }
-              }
-              checked.push((
-                map_prop.library_name.clone(),
-                map_prop.group_name.clone(),
-                map_prop.name.clone(),
-                map_prop.texture_name.clone(),
-              ));
-              continue 'prop;
-            } else {
-              panic!(
-                "texture {} not exists for prop {}/{}/{}",
-                texture_name, library.name, group.name, prop.name
-              );
-            }
+  /// Warns about props the map places that resolve to proplibs with different versions,
+  /// so the map would look different depending on the namespaces it is loaded with.
+  fn validate_proplib_versions(&self) {
+    let info = self.get_info().as_ref().unwrap();
+    let placed = self
+      .get_parsed()
+      .flat_map(|(_, map)| &map.static_geometry.props)
+      .map(|prop| (prop.library_name.as_str(), prop.group_name.as_str(), prop.name.as_str()))
+      .collect::<BTreeSet<_>>();
+    for message in find_version_conflicts(&info.namespaces, &placed, &self.proplib_versions) {
+      diagnostics::warning(format!("map {}: {}", info.name, message));
+    }
+  }
 
-            // let default_file = file_exists_case_insensitive(root.join(default_texture));
-            // if let Some(default_file) = &default_file {
-            //   // info!("{:?}", default_file);
-            //   default_file.to_owned()
-            // } else {
-            //   panic!("mesh {}/{}/{} ({:?}) default texture {} not exists", library.name, group.name, prop.name, mesh_file, default_texture);
-            // }
-
-            // for texture in &mesh.textures {
-            //   info!("texture {:?}", texture);
-            // }
-          } else if let Some(sprite) = &prop.sprite {
-            if let Some(images) = &proplib.images {
-              let image = images
-                .images
-                .iter()
-                .find(|image| image.name.to_lowercase() == sprite.file.to_lowercase());
-              // info!("texture_file: {:?}", image);
-              if let Some(image) = image {
-                // info!("{:?}", image);
-
-                let file = root.join(&image.diffuse);
-                let file = file_exists_case_insensitive(&file);
-                if let Some(_file) = &file {
-                } else {
-                  panic!("diffuse file {:?} for sprite {} not exists", file, image.name);
-                }
-
-                if let Some(alpha) = &image.alpha {
-                  let file = root.join(alpha);
-                  let file = file_exists_case_insensitive(&file);
-                  if let Some(_file) = &file {
-                  } else {
-                    panic!("alpha file {:?} for sprite {} not exists", file, image.name);
-                  }
-                }
-              } else {
-                error!("images: {:?}", images);
-                panic!(
-                  "texture mapping for sprite {:?} not exists for prop {}/{}/{}",
-                  sprite, library.name, group.name, prop.name
-                );
-              }
-              continue 'prop;
-            } else {
-              let file = root.join(&sprite.file);
-              let file = file_exists_case_insensitive(&file);
-              if let Some(_file) = &file {
-                continue 'prop;
-              } else {
-                panic!(
-                  "sprite {}/{}/{} file {:?} not exists",
-                  library.name, group.name, prop.name, sprite.file
-                );
-              }
-            }
-          } else {
-            unreachable!();
+  /// Warns about spawn points placed inside bonus regions or next to flags and keypoints.
+  /// Heights are ignored, as regions are usually flat and spawn points are placed slightly above ground.
+  pub fn validate_spawn_overlaps(&self) {
+    for (variant, map) in self.get_parsed() {
+      let name = self.get_variant_name(variant);
+
+      let mut protected = Vec::new();
+      if let Some(flags) = &map.ctf_flags {
+        protected.push(("blue flag".to_owned(), &flags.blue));
+        protected.push(("red flag".to_owned(), &flags.red));
+      }
+      if let Some(keypoints) = &map.dom_keypoints {
+        for keypoint in &keypoints.dom_keypoints {
+          protected.push((format!("keypoint {}", keypoint.name), &keypoint.position));
+        }
+      }
+
+      for spawn_point in &map.spawn_points.spawn_points {
+        for region in &map.bonus_regions.bonus_regions {
+          if region.contains_horizontal(&spawn_point.position) {
+            diagnostics::warning(format!(
+              "map {}: {} spawn point at {:?} is inside bonus region {}",
+              name, spawn_point.kind, spawn_point.position, region.name
+            ));
+          }
+        }
+
+        for (element, position) in &protected {
+          let distance = spawn_point.position.horizontal_distance(position);
+          if distance < SPAWN_PROTECTION_RADIUS {
+            diagnostics::warning(format!(
+              "map {}: {} spawn point at {:?} is {:.0} units away from {}",
+              name, spawn_point.kind, spawn_point.position, distance, element
+            ));
           }
-        } else {
-          panic!("prop {:?} not found", map_prop);
         }
       }
     }
+  }
+}
 
-    Ok(())
+/// Warns if a texture file referenced by a prop can't be read as an image, which the existence check misses.
+async fn probe_texture(file: &Path, library: &str, group: &str, prop: &str, texture: &str) {
+  if cli::args().skip_texture_probe {
+    return;
+  }
+
+  let result = match files::read(file).await {
+    Ok(data) => imaging::probe(&data).map(|_| ()),
+    Err(error) => Err(error.into()),
+  };
+  if let Err(error) = result {
+    diagnostics::warning(format!(
+      "prop {}/{}/{} texture {}: {} is not a valid image: {:#}",
+      library,
+      group,
+      prop,
+      texture,
+      file.display(),
+      error
+    ));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn map(root: &Path) -> MapResource {
+    MapResource {
+      root: root.to_path_buf(),
+      info: Some(ResourceInfo::for_test("maps.test", 1, 1)),
+      parsed: None,
+      variants: BTreeMap::new(),
+      proplibs: HashMap::new(),
+      proplib_versions: HashMap::new(),
+      map: None,
+      maps: BTreeMap::new(),
+      namespace: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn missing_map_is_listed_as_input() {
+    let root = tempfile::tempdir().unwrap();
+
+    // The scan reports missing input files, failing here would hide the others
+    let files = map(root.path()).input_files().await.unwrap();
+
+    assert_eq!(files, [root.path().join("map.xml")]);
+  }
+
+  #[tokio::test]
+  async fn collision_file_is_listed_once() {
+    let root = tempfile::tempdir().unwrap();
+    let content = r#"<map><collision-geometry src="collision.xml"/></map>"#;
+    std::fs::write(root.path().join("map.xml"), content).unwrap();
+    std::fs::write(root.path().join("map-night.xml"), content).unwrap();
+    let mut resource = map(root.path());
+    resource.maps.insert("night".to_owned(), PathBuf::from("map-night.xml"));
+
+    let files = resource.input_files().await.unwrap();
+
+    assert_eq!(files, [
+      root.path().join("map.xml"),
+      root.path().join("collision.xml"),
+      root.path().join("map-night.xml"),
+    ]);
+  }
+
+  #[tokio::test]
+  async fn split_collision_geometry_generates_the_same_map() {
+    cli::init_defaults();
+    let props = r#"<static-geometry><prop library-name="Land" group-name="group" name="prop"><position/><rotation/><texture-name>grass</texture-name></prop></static-geometry>"#;
+    let geometry =
+      "<collision-plane><width>10</width><length>20</length><position><x>1</x></position><rotation/></collision-plane>\
+      <collision-box><size><x>2</x><y>2</y><z>2</z></size><position/><rotation><z>1.5</z></rotation></collision-box>";
+    let inline = tempfile::tempdir().unwrap();
+    std::fs::write(
+      inline.path().join("map.xml"),
+      format!("<map>{props}<collision-geometry>{geometry}</collision-geometry></map>"),
+    )
+    .unwrap();
+    let split = tempfile::tempdir().unwrap();
+    std::fs::write(
+      split.path().join("map.xml"),
+      format!(r#"<map>{props}<collision-geometry src="collision.xml"/></map>"#),
+    )
+    .unwrap();
+    std::fs::write(
+      split.path().join("collision.xml"),
+      format!("<collision-geometry>{geometry}</collision-geometry>"),
+    )
+    .unwrap();
+
+    let mut outputs = Vec::new();
+    for root in [inline.path(), split.path()] {
+      let mut resource = map(root);
+      resource.parsed = Some(read_map(&root.join("map.xml")).await.unwrap());
+      outputs.push(resource.output_files().await.unwrap());
+    }
+
+    assert_eq!(outputs[0]["map.xml"], outputs[1]["map.xml"]);
+    assert_eq!(outputs[0]["collision.bin"], outputs[1]["collision.bin"]);
+  }
+
+  /// Proplib `name` defining a single `group/prop`.
+  #[allow(deprecated)]
+  fn library(name: &str, id: i64, version: i64) -> ProplibResource {
+    ProplibResource {
+      root: PathBuf::new(),
+      info: Some(ResourceInfo::for_test(name, id, version)),
+      name: Some(name.to_owned()),
+      namespace: None,
+      chunk_size: None,
+      include_extensions: Vec::new(),
+      exclude_extensions: Vec::new(),
+      library: Some(proplib::Library {
+        name: name.to_owned(),
+        prop_groups: vec![proplib::PropGroup {
+          name: "group".to_owned(),
+          props: vec![proplib::Prop {
+            name: "prop".to_owned(),
+            mesh: None,
+            sprite: None,
+          }],
+        }],
+      }),
+      images: None,
+    }
+  }
+
+  fn combination(gen: &str, theme: &str) -> BTreeMap<String, String> {
+    BTreeMap::from([
+      ("gen".to_owned(), gen.to_owned()),
+      (THEME_NAMESPACE.to_owned(), theme.to_owned()),
+    ])
+  }
+
+  fn placed() -> BTreeSet<(&'static str, &'static str, &'static str)> {
+    BTreeSet::from([("Land", "group", "prop")])
+  }
+
+  #[test]
+  fn themes_may_resolve_to_different_versions() {
+    let proplib_versions = HashMap::from([
+      (combination("1", "summer"), vec![library("Land", 10, 1)]),
+      (combination("1", "winter"), vec![library("Land", 11, 2)]),
+    ]);
+
+    let conflicts = find_version_conflicts(&HashMap::new(), &placed(), &proplib_versions);
+
+    assert!(conflicts.is_empty(), "{:?}", conflicts);
+  }
+
+  #[test]
+  fn mixed_gen_in_one_theme_is_reported() {
+    let proplib_versions = HashMap::from([
+      (combination("1", "summer"), vec![library("Land", 10, 1)]),
+      (combination("2", "summer"), vec![library("Land", 12, 3)]),
+      (combination("2", "winter"), vec![library("Land", 13, 4)]),
+    ]);
+
+    let conflicts = find_version_conflicts(&HashMap::new(), &placed(), &proplib_versions);
+
+    assert_eq!(conflicts.len(), 1, "{:?}", conflicts);
+    assert!(conflicts[0].starts_with("library Land resolves to 2 proplib versions for theme summer:"));
+    assert!(conflicts[0].contains("version 1 (10 "));
+    assert!(conflicts[0].contains("version 3 (12 "));
+    assert!(conflicts[0].ends_with("used by group/prop"));
+  }
+
+  #[test]
+  fn only_combinations_of_the_map_namespaces_are_compared() {
+    let proplib_versions = HashMap::from([
+      (combination("1", "summer"), vec![library("Land", 10, 1)]),
+      (combination("2", "summer"), vec![library("Land", 12, 3)]),
+    ]);
+    let namespaces = HashMap::from([("gen".to_owned(), "1".to_owned())]);
+
+    let conflicts = find_version_conflicts(&namespaces, &placed(), &proplib_versions);
+
+    assert!(conflicts.is_empty(), "{:?}", conflicts);
+  }
+
+  #[test]
+  fn libraries_without_placed_props_are_ignored() {
+    let proplib_versions = HashMap::from([
+      (combination("1", "summer"), vec![library("Land", 10, 1)]),
+      (combination("2", "summer"), vec![library("Land", 12, 3)]),
+    ]);
+
+    let conflicts = find_version_conflicts(&HashMap::new(), &BTreeSet::new(), &proplib_versions);
+
+    assert!(conflicts.is_empty(), "{:?}", conflicts);
   }
 }
@@ -18,20 +18,21 @@
 
 use std::collections::HashMap;
 use std::io::{self, Cursor};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use alternativa_protocol::protocol_buffer::{FinalCodec, ProtocolBuffer};
 use alternativa_protocol::Codec;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tara::TaraArchive;
-use tokio::fs;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::kind::{read_file, MetadataDetails, ResourceInfo};
+use crate::{compression, imaging};
 
-#[derive(Clone, Debug, Serialize, Deserialize, Codec)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema, Codec)]
 pub struct MultiframeTextureProperties {
   pub fps: f32,
   pub frame_height: i32,
@@ -41,15 +42,76 @@ pub struct MultiframeTextureProperties {
   pub frames: i16,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Either all properties, or only the frame count and columns of a grid of equally sized frames,
+/// the rest is then computed from the size of the diffuse image.
+/// Without `frames`, the image is a single row of square frames; without `columns`, all frames are in one row.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum MultiframeTextureLayout {
+  Manual(MultiframeTextureProperties),
+  Auto(MultiframeTextureAutoLayout),
+}
+
+/// Unknown fields are rejected, so a misspelled property isn't silently replaced by a computed one.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MultiframeTextureAutoLayout {
+  pub fps: f32,
+  pub frames: Option<i16>,
+  pub columns: Option<i32>,
+}
+
+/// Frame rate of short definitions that don't set `fps`.
+pub const DEFAULT_FPS: f32 = 30.0;
+
+impl Default for MultiframeTextureLayout {
+  fn default() -> Self {
+    MultiframeTextureLayout::Auto(MultiframeTextureAutoLayout {
+      fps: DEFAULT_FPS,
+      frames: None,
+      columns: None,
+    })
+  }
+}
+
+/// Reads the layout of a short definition from its `.properties` sidecar: `key=value` lines
+/// with the keys of `properties` in `resource.yaml`, `fps` defaults to [DEFAULT_FPS].
+pub fn parse_properties_file(content: &str, file: &Path) -> Result<MultiframeTextureLayout> {
+  let mut properties = serde_yaml::Mapping::new();
+  properties.insert("fps".into(), DEFAULT_FPS.into());
+  for (index, line) in content.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+      continue;
+    }
+    let Some((key, value)) = line.split_once('=') else {
+      bail!("{}:{}: expected key=value", file.display(), index + 1);
+    };
+    let value: serde_yaml::Value = serde_yaml::from_str(value.trim())
+      .with_context(|| format!("{}:{}: invalid value of {}", file.display(), index + 1, key.trim()))?;
+    properties.insert(key.trim().into(), value);
+  }
+
+  serde_yaml::from_value(serde_yaml::Value::Mapping(properties))
+    .with_context(|| format!("invalid multiframe texture properties in {}", file.display()))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct MultiframeTextureResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub diffuse: Option<PathBuf>,
   pub alpha: Option<PathBuf>,
-  pub properties: MultiframeTextureProperties,
+  pub properties: MultiframeTextureLayout,
+  /// `.properties` sidecar the layout of a short definition was read from.
+  #[serde(skip)]
+  pub properties_file: Option<PathBuf>,
+  #[serde(skip)]
+  pub resolved: Option<MultiframeTextureProperties>,
 }
 
 #[async_trait]
@@ -59,6 +121,17 @@ impl Resource for MultiframeTextureResource {
   }
 
   async fn init(&mut self, info: ResourceInfo) -> Result<()> {
+    self.resolved = Some(match &self.properties {
+      MultiframeTextureLayout::Manual(properties) => properties.clone(),
+      MultiframeTextureLayout::Auto(MultiframeTextureAutoLayout { fps, frames, columns }) => {
+        let diffuse = self.get_diffuse();
+        let data = read_file(&diffuse).await?;
+        let (_, image_width, image_height) =
+          imaging::probe(&data).with_context(|| format!("failed to read {}", diffuse.display()))?;
+        compute_properties(*fps, *frames, *columns, image_width as i32, image_height as i32)
+          .with_context(|| format!("resource {}: invalid frame layout of {}", info.name, diffuse.display()))?
+      }
+    });
     self.info = Some(info);
     Ok(())
   }
@@ -72,7 +145,15 @@ impl Resource for MultiframeTextureResource {
   }
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
-    Ok(vec![self.get_diffuse(), self.get_alpha()])
+    let mut files = vec![self.get_diffuse()];
+    files.extend(self.properties_file.clone());
+    // Alpha is optional unless explicitly set
+    let alpha = self.get_alpha();
+    if self.alpha.is_some() || alpha.try_exists()? {
+      files.push(alpha);
+    }
+
+    Ok(files)
   }
 
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
@@ -83,19 +164,24 @@ impl Resource for MultiframeTextureResource {
 
     let alpha = self.get_alpha();
     if alpha.try_exists()? {
-      archive.add_entry("a".to_owned(), fs::read(alpha).await.unwrap());
+      archive.add_entry("a".to_owned(), read_file(alpha).await?);
     }
 
     let diffuse = self.get_diffuse();
     if diffuse.try_exists()? {
-      archive.add_entry("i".to_owned(), fs::read(diffuse).await.unwrap());
+      archive.add_entry("i".to_owned(), read_file(diffuse).await?);
     }
 
     let mut data = Vec::new();
     archive.write(&mut data)?;
+    let data = compression::compress_archive(&self.info.as_ref().unwrap().name, data)?;
 
     Ok(HashMap::from([("image.tara".to_owned(), data)]))
   }
+
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    Ok(Some(MetadataDetails::MultiframeTexture(self.get_properties().clone())))
+  }
 }
 
 impl MultiframeTextureResource {
@@ -115,7 +201,7 @@ impl MultiframeTextureResource {
 
   pub fn get_alpha(&self) -> PathBuf {
     self
-      .diffuse
+      .alpha
       .clone()
       .map(|file| {
         if file.starts_with(&self.root) {
@@ -127,9 +213,14 @@ impl MultiframeTextureResource {
       .unwrap_or_else(|| self.get_root().join("alpha.jpg"))
   }
 
+  /// Returns the properties, computed from the diffuse image by [`Resource::init`] if needed.
+  pub fn get_properties(&self) -> &MultiframeTextureProperties {
+    self.resolved.as_ref().expect("resource is not initialized")
+  }
+
   fn get_properties_file(&self) -> io::Result<Vec<u8>> {
     let mut buffer = ProtocolBuffer::new();
-    self.properties.encode(&mut buffer)?;
+    self.get_properties().encode(&mut buffer)?;
 
     let mut data = Cursor::new(Vec::new());
     buffer.encode(&mut data)?;
@@ -138,3 +229,90 @@ impl MultiframeTextureResource {
     Ok(data.get_ref()[position..].to_vec())
   }
 }
+
+/// Splits an image into `columns` columns and as many rows as needed for `frames`.
+fn compute_properties(
+  fps: f32,
+  frames: Option<i16>,
+  columns: Option<i32>,
+  image_width: i32,
+  image_height: i32,
+) -> Result<MultiframeTextureProperties> {
+  let frames = match frames {
+    Some(frames) => frames,
+    None => {
+      if image_height <= 0 || image_width % image_height != 0 {
+        bail!(
+          "image of {}x{} is not a single row of square frames, set frames",
+          image_width,
+          image_height
+        );
+      }
+      i16::try_from(image_width / image_height).context("too many frames")?
+    }
+  };
+  let columns = columns.unwrap_or(frames as i32);
+  if frames <= 0 || columns <= 0 {
+    bail!("frames ({}) and columns ({}) must be positive", frames, columns);
+  }
+  let rows = (frames as u32).div_ceil(columns as u32) as i32;
+  if image_width % columns != 0 || image_height % rows != 0 {
+    bail!(
+      "image of {}x{} can't be divided into {} columns and {} rows",
+      image_width,
+      image_height,
+      columns,
+      rows
+    );
+  }
+
+  Ok(MultiframeTextureProperties {
+    fps,
+    frame_height: image_height / rows,
+    frame_width: image_width / columns,
+    image_height,
+    image_width,
+    frames,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn grid_is_divided_into_columns_and_rows() {
+    let properties = compute_properties(24.0, Some(6), Some(3), 192, 128).unwrap();
+    assert_eq!(properties.frame_width, 64);
+    assert_eq!(properties.frame_height, 64);
+    assert_eq!(properties.frames, 6);
+    assert_eq!(properties.fps, 24.0);
+  }
+
+  #[test]
+  fn indivisible_image_is_rejected() {
+    let error = compute_properties(DEFAULT_FPS, Some(4), Some(3), 200, 100).unwrap_err();
+    assert!(
+      error.to_string().contains("can't be divided into 3 columns and 2 rows"),
+      "{}",
+      error
+    );
+  }
+
+  #[test]
+  fn default_layout_is_one_row_of_square_frames() {
+    let properties = compute_properties(DEFAULT_FPS, None, None, 256, 64).unwrap();
+    assert_eq!(properties.frames, 4);
+    assert_eq!(properties.frame_width, 64);
+    assert_eq!(properties.frame_height, 64);
+
+    let error = compute_properties(DEFAULT_FPS, None, None, 200, 64).unwrap_err();
+    assert!(error.to_string().contains("set frames"), "{}", error);
+  }
+
+  #[test]
+  fn unknown_properties_are_rejected() {
+    let error = parse_properties_file("frame=4\n", Path::new("fire.properties")).unwrap_err();
+    assert!(format!("{:#}", error).contains("fire.properties"), "{:#}", error);
+  }
+}
@@ -16,16 +16,22 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use glob::Pattern;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{insert_output_file, read_file, validate_image_manifest, ResourceInfo};
+use crate::{cli, diagnostics, get_texture_map_names, parse_3ds};
+
+/// Extensions of the model files an Object3D without `object` picks up from its directory.
+pub const MODEL_EXTENSIONS: [&str; 2] = ["3ds", "a3d"];
 
 #[derive(Debug, Serialize)]
 #[serde(rename = "images")]
@@ -46,20 +52,29 @@ pub struct ImageXml {
   pub alpha: Option<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum Object3DImage {
   Simple(PathBuf),
-  Complex { diffuse: PathBuf, alpha: PathBuf },
+  Complex {
+    diffuse: PathBuf,
+    alpha: PathBuf,
+  },
+  /// Diffuse-only images for every file matching the pattern, named by file stem.
+  /// The key of the entry itself is not used as an image name.
+  Glob {
+    glob: String,
+  },
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Object3DResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
-  pub id: Option<u32>,
   pub object: Option<PathBuf>,
   pub images: HashMap<String, Object3DImage>,
 }
@@ -84,8 +99,8 @@ impl Resource for Object3DResource {
   }
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
-    let mut files = vec![self.get_object()];
-    for image in self.images.values() {
+    let mut files = vec![self.get_object()?];
+    for image in self.get_images()?.values() {
       match image {
         Object3DImage::Simple(diffuse) => {
           files.push(self.root.join(diffuse.clone()));
@@ -94,19 +109,106 @@ impl Resource for Object3DResource {
           files.push(self.root.join(diffuse.clone()));
           files.push(self.root.join(alpha.clone()));
         }
+        Object3DImage::Glob { .. } => unreachable!("globs are expanded"),
       }
     }
 
     Ok(files)
   }
 
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    let mut errors = ValidationErrors::default();
+    let name = &self.info.as_ref().unwrap().name;
+    let images = self.get_images()?;
+    let image_files = images
+      .iter()
+      .map(|(image, files)| match files {
+        Object3DImage::Simple(diffuse) => (image.as_str(), diffuse.to_string_lossy().into_owned(), None),
+        Object3DImage::Complex { diffuse, alpha } => (
+          image.as_str(),
+          diffuse.to_string_lossy().into_owned(),
+          Some(alpha.to_string_lossy().into_owned()),
+        ),
+        Object3DImage::Glob { .. } => unreachable!("globs are expanded"),
+      })
+      .collect::<Vec<_>>();
+    validate_image_manifest(
+      name,
+      image_files
+        .iter()
+        .map(|(image, diffuse, alpha)| (*image, diffuse.as_str(), alpha.as_deref())),
+      &mut errors,
+    )?;
+
+    let object = self.get_object()?;
+    // Missing files are reported with the other missing input files, other formats pass through as is
+    let is_3ds = object
+      .extension()
+      .is_some_and(|extension| extension.eq_ignore_ascii_case("3ds"));
+    if !is_3ds || !object.try_exists()? {
+      return Ok(errors.into_vec());
+    }
+
+    let error = |message: String| ValidationError {
+      resource: name.clone(),
+      prop: None,
+      message,
+      file: None,
+    };
+    let main = match parse_3ds(&read_file(&object).await?, &object) {
+      Ok(main) => main,
+      Err(parse_error) => {
+        errors.push(error(format!("{:#}", parse_error)))?;
+        return Ok(errors.into_vec());
+      }
+    };
+
+    // The client looks images up by the texture map names of the model
+    let texture_maps = get_texture_map_names(&main)
+      .into_iter()
+      .map(|texture_map| texture_map.to_lowercase())
+      .collect::<BTreeSet<_>>();
+    let images = images
+      .into_keys()
+      .map(|image| image.to_lowercase())
+      .collect::<BTreeSet<_>>();
+    let mut problems = Vec::new();
+    if texture_maps.is_empty() {
+      problems.push(format!("model {} has no texture map", object.display()));
+    }
+    for texture_map in texture_maps.difference(&images) {
+      problems.push(format!(
+        "texture map {} of model {} has no image",
+        texture_map,
+        object.display()
+      ));
+    }
+    for image in images.difference(&texture_maps) {
+      problems.push(format!(
+        "image {} is not a texture map of model {}",
+        image,
+        object.display()
+      ));
+    }
+
+    for problem in problems {
+      if cli::args().strict {
+        errors.push(error(problem))?;
+      } else {
+        diagnostics::warning(format!("resource {}: {}", name, problem));
+      }
+    }
+
+    Ok(errors.into_vec())
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
     let mut files = HashMap::new();
     files.insert(
       "images.xml".to_owned(),
       quick_xml::se::to_string(&ImagesXml {
         images: self
-          .images
+          .get_images()?
           .iter()
           .map(|(name, image)| match image {
             Object3DImage::Simple(diffuse) => ImageXml {
@@ -119,17 +221,22 @@ impl Resource for Object3DResource {
               diffuse: diffuse.clone().file_name().unwrap().to_string_lossy().to_string(),
               alpha: Some(alpha.clone().file_name().unwrap().to_string_lossy().to_string()),
             },
+            Object3DImage::Glob { .. } => unreachable!("globs are expanded"),
           })
           .collect(),
       })?
       .into_bytes(),
     );
 
+    // Input files are flattened by file name, so files from different directories can collide
+    let info = self.info.as_ref().unwrap();
     for file in self.input_files().await? {
-      files.insert(
+      insert_output_file(
+        &mut files,
+        info,
         file.file_name().unwrap().to_str().unwrap().to_owned(),
-        fs::read(file).await.unwrap(),
-      );
+        read_file(file).await?,
+      )?;
     }
 
     Ok(files)
@@ -137,17 +244,87 @@ impl Resource for Object3DResource {
 }
 
 impl Object3DResource {
-  pub fn get_object(&self) -> PathBuf {
-    self
-      .object
-      .clone()
-      .map(|file| {
-        if file.starts_with(&self.root) {
-          file
-        } else {
-          self.get_root().join(file)
+  /// Returns `object`, or the only model file in the resource directory if it is not set.
+  pub fn get_object(&self) -> Result<PathBuf> {
+    if let Some(file) = &self.object {
+      if file.starts_with(&self.root) {
+        return Ok(file.clone());
+      }
+      return Ok(self.get_root().join(file));
+    }
+
+    let mut models = Vec::new();
+    for entry in std::fs::read_dir(&self.root).with_context(|| format!("failed to read {}", self.root.display()))? {
+      let path = entry?.path();
+      let is_model = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| MODEL_EXTENSIONS.contains(&extension.to_lowercase().as_str()));
+      if is_model && path.is_file() {
+        models.push(path);
+      }
+    }
+    models.sort();
+    match models.len() {
+      1 => Ok(models.remove(0)),
+      0 => bail!(
+        "{} has no {} model file, set `object`",
+        self.root.display(),
+        MODEL_EXTENSIONS.join(" or ")
+      ),
+      _ => bail!(
+        "{} has more than one model file, set `object` to one of {}",
+        self.root.display(),
+        models
+          .iter()
+          .map(|model| model.file_name().unwrap().to_string_lossy())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+    }
+  }
+
+  /// Returns `images` with glob entries expanded into one simple entry per matched file.
+  /// Explicit entries take precedence over glob matches with the same name,
+  /// while two globs matching files with the same name are an error.
+  pub fn get_images(&self) -> Result<BTreeMap<String, Object3DImage>> {
+    let mut images = BTreeMap::new();
+    let mut globbed = BTreeMap::new();
+    for (name, image) in &self.images {
+      let Object3DImage::Glob { glob } = image else {
+        images.insert(name.clone(), image.clone());
+        continue;
+      };
+
+      let pattern = Pattern::escape(self.root.to_str().unwrap()) + "/" + glob;
+      for file in glob::glob(&pattern).with_context(|| format!("invalid images glob {}", glob))? {
+        let file = file?;
+        if !file.is_file() {
+          continue;
         }
-      })
-      .unwrap_or_else(|| self.get_root().join("object.3ds"))
+        let stem = file
+          .file_stem()
+          .and_then(|stem| stem.to_str())
+          .ok_or_else(|| anyhow!("invalid image file name {}", file.display()))?
+          .to_owned();
+        let relative = file.strip_prefix(&self.root)?.to_path_buf();
+        if let Some(previous) = globbed
+          .insert(stem.clone(), relative.clone())
+          .filter(|previous| *previous != relative)
+        {
+          bail!(
+            "image {} is matched by globs as both {} and {}",
+            stem,
+            previous.display(),
+            relative.display()
+          );
+        }
+      }
+    }
+    for (name, file) in globbed {
+      images.entry(name).or_insert(Object3DImage::Simple(file));
+    }
+
+    Ok(images)
   }
 }
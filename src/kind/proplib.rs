@@ -16,38 +16,54 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tara::TaraArchive;
-use tokio::fs;
+use tracing::debug;
 use walkdir::WalkDir;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
-use crate::RESOURCE_DEFINITION_FILE;
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{
+  insert_output_file, is_hidden_entry, read_file, read_file_to_string, validate_image_manifest, ResourceInfo,
+};
+use crate::{
+  cli, compression, diagnostics, file_exists_case_insensitive, get_texture_map_name, missing_file_message, parse_3ds,
+  parse_xml, RESOURCE_DEFINITION_FILE,
+};
 
-#[derive(Debug, Deserialize)]
-#[serde(rename = "library")]
-pub struct LibraryXml {
-  #[serde(rename = "@name")]
-  pub name: String,
-}
+/// Namespaces maps validate their props against proplibs by, proplibs without them are not validated.
+pub const PROPLIB_NAMESPACES: [&str; 2] = ["gen", "theme"];
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ProplibResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub name: Option<String>,
 
   #[deprecated]
   pub namespace: Option<String>,
+  /// Splits `library.tara` into numbered chunks of at most this many payload bytes.
+  pub chunk_size: Option<usize>,
+  /// Only archive files with these extensions, all files if empty. `library.xml` and `images.xml` are always archived.
+  #[serde(default)]
+  pub include_extensions: Vec<String>,
+  /// Don't archive files with these extensions, e.g. editor backups and source images kept next to the assets.
+  #[serde(default)]
+  pub exclude_extensions: Vec<String>,
 
   #[serde(skip)]
   pub library: Option<Library>,
@@ -64,10 +80,42 @@ impl Resource for ProplibResource {
   async fn init(&mut self, info: ResourceInfo) -> Result<()> {
     self.info = Some(info);
 
-    let library = self.get_root().join("library.xml");
-    let library = fs::read_to_string(library).await.unwrap();
-    let library: LibraryXml = quick_xml::de::from_str(&library)?;
-    self.name = Some(library.name);
+    let file = self.get_root().join("library.xml");
+    let library = read_file_to_string(&file).await?;
+    let library: Library = parse_xml(&library, &file)?;
+
+    let file = self.get_root().join("images.xml");
+    if file.try_exists()? {
+      debug!("found images.xml for {}", self.info.as_ref().unwrap().name);
+      let images = read_file_to_string(&file).await?;
+      self.images = Some(parse_xml(&images, &file)?);
+    }
+
+    // Maps resolve proplibs by the library.xml name, while the id is derived from the path
+    let info = self.info.as_ref().unwrap();
+    let missing = PROPLIB_NAMESPACES
+      .iter()
+      .filter(|key| !info.namespaces.contains_key(**key))
+      .collect::<Vec<_>>();
+    if !missing.is_empty() {
+      let message = format!(
+        "proplib {}: missing namespaces {:?}, maps can't validate props against it",
+        info.name, missing
+      );
+      if cli::args().strict_namespaces {
+        bail!(message);
+      }
+      diagnostics::warning(message);
+    }
+    let directory_name = info.name.rsplit('.').next().unwrap();
+    if library.name != directory_name {
+      diagnostics::warning(format!(
+        "proplib {}: library.xml name {:?} doesn't match directory name {:?}, maps must reference it as {:?}",
+        info.name, library.name, directory_name, library.name
+      ));
+    }
+    self.name = Some(library.name.clone());
+    self.library = Some(library);
 
     Ok(())
   }
@@ -82,7 +130,13 @@ impl Resource for ProplibResource {
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
     let mut files = Vec::new();
-    for entry in WalkDir::new(self.get_root()) {
+    // Directory iteration order differs between platforms, and `--version-order input` digests files in this order.
+    // Hidden files like `.DS_Store` would end up in `library.tara`
+    for entry in WalkDir::new(self.get_root())
+      .sort_by_file_name()
+      .into_iter()
+      .filter_entry(|entry| !is_hidden_entry(entry))
+    {
       let entry = entry?;
       if entry.file_type().is_dir() {
         continue;
@@ -90,26 +144,313 @@ impl Resource for ProplibResource {
       if entry.file_name() == RESOURCE_DEFINITION_FILE {
         continue;
       }
+      if !self.archives(entry.path()) {
+        continue;
+      }
 
       files.push(entry.path().to_path_buf())
     }
     Ok(files)
   }
 
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    let root = self.get_root();
+    let mut errors = ValidationErrors::default();
+    validate_image_manifest(
+      &self.info.as_ref().unwrap().name,
+      self
+        .images
+        .iter()
+        .flat_map(|images| &images.images)
+        .map(|image| (image.name.as_str(), image.diffuse.as_str(), image.alpha.as_deref())),
+      &mut errors,
+    )?;
+    for image in self.images.iter().flat_map(|images| &images.images) {
+      let mut files = vec![("diffuse", root.join(&image.diffuse))];
+      if let Some(alpha) = &image.alpha {
+        files.push(("alpha", root.join(alpha)));
+      }
+      for (kind, file) in files {
+        if file_exists_case_insensitive(&file).is_none() {
+          errors.push(ValidationError {
+            resource: self.info.as_ref().unwrap().name.clone(),
+            prop: None,
+            message: format!(
+              "{} file for texture {}: {}",
+              kind,
+              image.name,
+              missing_file_message(&file)
+            ),
+            file: Some(file),
+          })?;
+        }
+      }
+    }
+
+    if cli::args().validate_meshes {
+      self.validate_meshes(&mut errors).await?;
+    }
+
+    Ok(errors.into_vec())
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
-    let mut archive = TaraArchive::new();
+    let info = self.info.as_ref().unwrap();
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    let root = self.get_root();
+    let normalize_case = cli::args().normalize_case;
     for file in self.input_files().await? {
-      archive.add_entry(
-        file.file_name().unwrap().to_str().unwrap().to_owned(),
-        fs::read(file).await.unwrap(),
-      );
+      let mut name = entry_name(&root, &file)?;
+      if normalize_case {
+        name = name.to_lowercase();
+      }
+      if !names.insert(name.clone()) {
+        bail!("resource {} archives {} more than once", info.name, name);
+      }
+      let mut data = read_file(&file).await?;
+      if normalize_case && (name == "library.xml" || name == "images.xml") {
+        data = lowercase_references(&data).with_context(|| format!("failed to normalize {}", file.display()))?;
+      }
+      entries.push((name, data));
+    }
+
+    let Some(chunk_size) = self.chunk_size else {
+      return Ok(HashMap::from([(
+        "library.tara".to_owned(),
+        write_archive(&info.name, entries)?,
+      )]));
+    };
+
+    // Greedy bin-packing over sorted entries, so the same input always produces the same chunks.
+    // An entry larger than the chunk size gets a chunk of its own.
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut chunks: Vec<Vec<(String, Vec<u8>)>> = Vec::new();
+    let mut chunk_total = 0;
+    for entry in entries {
+      let size = entry.1.len();
+      match chunks.last_mut() {
+        Some(chunk) if chunk_total + size <= chunk_size => {
+          chunk_total += size;
+          chunk.push(entry);
+        }
+        _ => {
+          chunk_total = size;
+          chunks.push(vec![entry]);
+        }
+      }
+    }
+
+    let mut files = HashMap::new();
+    let mut index = ChunkIndex { chunks: Vec::new() };
+    for (number, chunk) in chunks.into_iter().enumerate() {
+      let file = format!("library.{}.tara", number);
+      let entries = chunk.iter().map(|(name, _)| name.clone()).collect();
+      let data = write_archive(&format!("{} {}", info.name, file), chunk)?;
+      index.chunks.push(Chunk {
+        file: file.clone(),
+        size: data.len(),
+        entries,
+      });
+      insert_output_file(&mut files, info, file, data)?;
+    }
+    debug!("split proplib into {} chunks", index.chunks.len());
+    insert_output_file(
+      &mut files,
+      info,
+      "library.index.json".to_owned(),
+      serde_json::to_vec_pretty(&index)?,
+    )?;
+
+    Ok(files)
+  }
+}
+
+impl ProplibResource {
+  /// Whether `file` passes `include_extensions` and `exclude_extensions`, compared ignoring case and a leading dot.
+  fn archives(&self, file: &Path) -> bool {
+    let root = self.get_root();
+    if file == root.join("library.xml") || file == root.join("images.xml") {
+      return true;
+    }
+
+    let extension = file
+      .extension()
+      .and_then(|extension| extension.to_str())
+      .unwrap_or_default();
+    let matches = |extensions: &[String]| {
+      extensions
+        .iter()
+        .any(|listed| listed.trim_start_matches('.').eq_ignore_ascii_case(extension))
+    };
+    (self.include_extensions.is_empty() || matches(&self.include_extensions)) && !matches(&self.exclude_extensions)
+  }
+
+  /// Checks that every mesh exists and has a default texture map whose file exists,
+  /// which maps use for props placed without a texture name.
+  async fn validate_meshes(&self, errors: &mut ValidationErrors) -> Result<()> {
+    let root = self.get_root();
+    let info = self.info.as_ref().unwrap();
+    let library = self.library.as_ref().unwrap();
+    for group in &library.prop_groups {
+      for prop in &group.props {
+        let Some(mesh) = &prop.mesh else {
+          continue;
+        };
+        let error = |message: String, file: Option<PathBuf>| ValidationError {
+          resource: info.name.clone(),
+          prop: Some(format!("{}/{}/{}", library.name, group.name, prop.name)),
+          message,
+          file,
+        };
+
+        let mesh_path = root.join(&mesh.file);
+        let Some(mesh_file) = file_exists_case_insensitive(&mesh_path) else {
+          errors.push(error(
+            format!("mesh file {}", missing_file_message(&mesh_path)),
+            Some(mesh_path),
+          ))?;
+          continue;
+        };
+
+        let data = read_file(&mesh_file).await?;
+        let main = match parse_3ds(&data, &mesh_file) {
+          Ok(main) => main,
+          Err(parse_error) => {
+            errors.push(error(format!("{:#}", parse_error), Some(mesh_file.clone())))?;
+            continue;
+          }
+        };
+        let Some(default_texture) = get_texture_map_name(&main) else {
+          errors.push(error(
+            format!("mesh {} has no default texture map", mesh_file.display()),
+            None,
+          ))?;
+          continue;
+        };
+
+        // Default texture maps name an image of images.xml if there is one, a file otherwise
+        let texture_path = match &self.images {
+          Some(images) => {
+            let image = images
+              .images
+              .iter()
+              .find(|image| image.name.to_lowercase() == default_texture.to_lowercase());
+            let Some(image) = image else {
+              errors.push(error(
+                format!(
+                  "default texture {} of mesh {} has no images.xml mapping",
+                  default_texture,
+                  mesh_file.display()
+                ),
+                None,
+              ))?;
+              continue;
+            };
+            root.join(&image.diffuse)
+          }
+          None => root.join(&default_texture),
+        };
+        if file_exists_case_insensitive(&texture_path).is_none() {
+          errors.push(error(
+            format!(
+              "default texture of mesh {}: {}",
+              mesh_file.display(),
+              missing_file_message(&texture_path)
+            ),
+            Some(texture_path),
+          ))?;
+        }
+      }
     }
 
-    let mut data = Vec::new();
-    archive.write(&mut data)?;
+    Ok(())
+  }
+}
+
+/// Returns the `library.tara` entry name of a file, its path relative to the proplib with `/` separators.
+/// Entries keep the path as library.xml and images.xml reference them,
+/// so files with the same name in different directories don't collide.
+fn entry_name(root: &Path, file: &Path) -> Result<String> {
+  let relative = file
+    .strip_prefix(root)
+    .with_context(|| format!("{} is outside of proplib {}", file.display(), root.display()))?;
+  let components = relative
+    .components()
+    .map(|component| {
+      let component = component.as_os_str();
+      component
+        .to_str()
+        .with_context(|| format!("{} has a non UTF-8 name", file.display()))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  Ok(components.join("/"))
+}
+
+/// Attributes of `library.xml` and `images.xml` that reference files or textures, as (element, attribute).
+const REFERENCE_ATTRIBUTES: [(&str, &str); 7] = [
+  ("mesh", "file"),
+  ("sprite", "file"),
+  ("texture", "name"),
+  ("texture", "diffuse-map"),
+  ("image", "name"),
+  ("image", "new-name"),
+  ("image", "alpha"),
+];
+
+/// Rewrites an XML file with the [REFERENCE_ATTRIBUTES] lowercased, for `--normalize-case`.
+fn lowercase_references(data: &[u8]) -> Result<Vec<u8>> {
+  let mut reader = Reader::from_reader(data);
+  let mut writer = Writer::new(Vec::new());
+  loop {
+    match reader.read_event()? {
+      Event::Eof => break,
+      Event::Start(element) => writer.write_event(Event::Start(lowercase_attributes(&element)?))?,
+      Event::Empty(element) => writer.write_event(Event::Empty(lowercase_attributes(&element)?))?,
+      event => writer.write_event(event)?,
+    }
+  }
+  Ok(writer.into_inner())
+}
+
+fn lowercase_attributes<'a>(element: &'a BytesStart) -> Result<BytesStart<'a>> {
+  let name = std::str::from_utf8(element.name().into_inner())?;
+  let mut lowercased = BytesStart::new(name);
+  for attribute in element.attributes() {
+    let attribute = attribute?;
+    let key = std::str::from_utf8(attribute.key.into_inner())?;
+    let value = attribute.unescape_value()?;
+    if REFERENCE_ATTRIBUTES.contains(&(name, key)) {
+      lowercased.push_attribute((key, value.to_lowercase().as_str()));
+    } else {
+      lowercased.push_attribute((key, value.as_ref()));
+    }
+  }
+  Ok(lowercased)
+}
 
-    Ok(HashMap::from([("library.tara".to_owned(), data)]))
+fn write_archive(name: &str, entries: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+  let mut archive = TaraArchive::new();
+  for (name, data) in entries {
+    archive.add_entry(name, data);
   }
+
+  let mut data = Vec::new();
+  archive.write(&mut data)?;
+  compression::compress_archive(name, data)
+}
+
+/// Lists the chunks of a split `library.tara` in the order the client should concatenate them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChunkIndex {
+  pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chunk {
+  pub file: String,
+  pub size: usize,
+  pub entries: Vec<String>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -181,3 +522,94 @@ pub struct Image {
   #[serde(rename = "@alpha")]
   pub alpha: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[allow(deprecated)]
+  fn proplib(root: &Path) -> ProplibResource {
+    ProplibResource {
+      root: root.to_path_buf(),
+      info: Some(ResourceInfo::for_test("proplibs.test", 1, 1)),
+      name: None,
+      namespace: None,
+      chunk_size: None,
+      include_extensions: Vec::new(),
+      exclude_extensions: Vec::new(),
+      library: None,
+      images: None,
+    }
+  }
+
+  fn write_files(root: &Path, files: &[&str]) {
+    for file in files {
+      let path = root.join(file);
+      std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+      std::fs::write(path, file.as_bytes()).unwrap();
+    }
+  }
+
+  #[tokio::test]
+  async fn entries_keep_relative_paths() {
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[
+      "library.xml",
+      "hi/tex.jpg",
+      "lo/tex.jpg",
+      "lo/mesh/prop.3ds",
+    ]);
+
+    let resource = proplib(root.path());
+    let mut names = resource
+      .input_files()
+      .await
+      .unwrap()
+      .iter()
+      .map(|file| entry_name(root.path(), file).unwrap())
+      .collect::<Vec<_>>();
+    names.sort();
+
+    // Same names as library.xml uses in `file` and `diffuse-map`
+    assert_eq!(names, ["hi/tex.jpg", "library.xml", "lo/mesh/prop.3ds", "lo/tex.jpg"]);
+  }
+
+  #[test]
+  fn entry_name_rejects_files_outside_of_the_proplib() {
+    let root = Path::new("/resources/proplibs/test");
+
+    assert_eq!(entry_name(root, &root.join("hi/tex.jpg")).unwrap(), "hi/tex.jpg");
+    assert!(entry_name(root, Path::new("/resources/proplibs/other/tex.jpg")).is_err());
+  }
+
+  #[tokio::test]
+  async fn input_files_are_sorted_by_file_name() {
+    let root = tempfile::tempdir().unwrap();
+    // Created out of order, so the directory order of most file systems differs
+    write_files(root.path(), &["z.jpg", "b/a.jpg", "a.jpg", RESOURCE_DEFINITION_FILE]);
+
+    let files = proplib(root.path()).input_files().await.unwrap();
+
+    assert_eq!(files, [
+      root.path().join("a.jpg"),
+      root.path().join("b/a.jpg"),
+      root.path().join("z.jpg"),
+    ]);
+  }
+
+  #[tokio::test]
+  async fn input_files_skip_hidden_files() {
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[
+      "library.xml",
+      ".DS_Store",
+      ".git/config",
+      "hi/.tex.jpg.swp",
+      "hi/tex.jpg",
+    ]);
+
+    let files = proplib(root.path()).input_files().await.unwrap();
+
+    assert_eq!(files, [root.path().join("hi/tex.jpg"), root.path().join("library.xml")]);
+  }
+}
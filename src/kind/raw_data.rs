@@ -0,0 +1,112 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Resource;
+use crate::kind::{insert_output_file, read_file, ResourceInfo};
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum RawDataFile {
+  /// Written under its own file name.
+  Simple(PathBuf),
+  Named {
+    file: PathBuf,
+    output: String,
+  },
+}
+
+/// Binary files shipped verbatim, without any processing.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RawDataResource {
+  #[serde(skip_deserializing)]
+  #[schemars(skip)]
+  pub root: PathBuf,
+  #[serde(skip_deserializing)]
+  #[schemars(skip)]
+  pub info: Option<ResourceInfo>,
+  /// Defaults to a single `data.bin`.
+  #[serde(default)]
+  pub files: Vec<RawDataFile>,
+}
+
+#[async_trait]
+impl Resource for RawDataResource {
+  fn init_root(&mut self, root: PathBuf) {
+    self.root = root;
+  }
+
+  async fn init(&mut self, info: ResourceInfo) -> Result<()> {
+    self.info = Some(info);
+    Ok(())
+  }
+
+  fn get_root(&self) -> PathBuf {
+    self.root.clone()
+  }
+
+  fn get_info(&self) -> &Option<ResourceInfo> {
+    &self.info
+  }
+
+  async fn input_files(&self) -> Result<Vec<PathBuf>> {
+    Ok(self.get_files().into_iter().map(|(file, _)| file).collect())
+  }
+
+  async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
+    let info = self.info.as_ref().unwrap();
+    let mut files = HashMap::new();
+    for (file, output) in self.get_files() {
+      insert_output_file(&mut files, info, output, read_file(file).await?)?;
+    }
+
+    Ok(files)
+  }
+}
+
+impl RawDataResource {
+  /// Returns the input files with the names they are written under.
+  pub fn get_files(&self) -> Vec<(PathBuf, String)> {
+    if self.files.is_empty() {
+      return vec![(self.get_root().join("data.bin"), "data.bin".to_owned())];
+    }
+
+    self
+      .files
+      .iter()
+      .map(|file| {
+        let (file, output) = match file {
+          RawDataFile::Simple(file) => (file, file.file_name().unwrap().to_string_lossy().to_string()),
+          RawDataFile::Named { file, output } => (file, output.clone()),
+        };
+        if file.starts_with(&self.root) {
+          (file.clone(), output)
+        } else {
+          (self.get_root().join(file), output)
+        }
+      })
+      .collect()
+  }
+}
@@ -17,23 +17,29 @@
  */
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{copy_output_file, read_file, MetadataDetails, ResourceInfo};
+use crate::{cli, files};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SoundResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub sound: Option<PathBuf>,
+  /// Container the sound is shipped in, as `sound.<format>`. Defaults to SWF-wrapped audio.
+  pub format: Option<SoundFormat>,
 }
 
 #[async_trait]
@@ -59,12 +65,44 @@ impl Resource for SoundResource {
     Ok(vec![self.get_sound()])
   }
 
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    let mut errors = ValidationErrors::default();
+    validate_sound_format(
+      &self.info.as_ref().unwrap().name,
+      &self.get_sound(),
+      self.get_format(),
+      &mut errors,
+    )
+    .await?;
+    Ok(errors.into_vec())
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
     Ok(HashMap::from([(
-      "sound.swf".to_owned(),
-      fs::read(self.get_sound()).await.unwrap(),
+      self.get_output_name(),
+      read_file(self.get_sound()).await?,
     )]))
   }
+
+  async fn write_outputs(&self, dir: &Path) -> Result<Vec<String>> {
+    copy_output_file(&self.get_sound(), dir, &self.get_output_name()).await
+  }
+
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    let data = files::read(self.get_sound()).await?;
+    let format = SoundFormat::detect(&data);
+    Ok(Some(MetadataDetails::Sound(SoundMetadata {
+      format,
+      duration: format.and_then(|format| format.duration(&data)),
+    })))
+  }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SoundMetadata {
+  pub format: Option<SoundFormat>,
+  /// Seconds, if it can be read from the headers.
+  pub duration: Option<f32>,
 }
 
 impl SoundResource {
@@ -81,4 +119,121 @@ impl SoundResource {
       })
       .unwrap_or_else(|| self.get_root().join("sound.mp3"))
   }
+
+  pub fn get_format(&self) -> SoundFormat {
+    self.format.unwrap_or(SoundFormat::Swf)
+  }
+
+  fn get_output_name(&self) -> String {
+    format!("sound.{}", self.get_format().extension())
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundFormat {
+  Swf,
+  Mp3,
+  Ogg,
+  Wav,
+}
+
+impl SoundFormat {
+  /// Detects the container format from the leading bytes of a file.
+  pub fn detect(data: &[u8]) -> Option<SoundFormat> {
+    match data {
+      [b'F' | b'C' | b'Z', b'W', b'S', ..] => Some(SoundFormat::Swf),
+      [b'I', b'D', b'3', ..] => Some(SoundFormat::Mp3),
+      [0xff, second, ..] if *second & 0xe0 == 0xe0 => Some(SoundFormat::Mp3),
+      [b'O', b'g', b'g', b'S', ..] => Some(SoundFormat::Ogg),
+      [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(SoundFormat::Wav),
+      _ => None,
+    }
+  }
+
+  /// Returns the format usually stored in files with `extension`.
+  pub fn from_extension(extension: &str) -> Option<SoundFormat> {
+    match extension.to_lowercase().as_str() {
+      "swf" => Some(SoundFormat::Swf),
+      "mp3" => Some(SoundFormat::Mp3),
+      "ogg" => Some(SoundFormat::Ogg),
+      "wav" => Some(SoundFormat::Wav),
+      _ => None,
+    }
+  }
+
+  pub fn extension(&self) -> &'static str {
+    match self {
+      SoundFormat::Swf => "swf",
+      SoundFormat::Mp3 => "mp3",
+      SoundFormat::Ogg => "ogg",
+      SoundFormat::Wav => "wav",
+    }
+  }
+
+  /// Returns the duration in seconds, if it can be read from the headers without decoding.
+  pub fn duration(&self, data: &[u8]) -> Option<f32> {
+    match self {
+      SoundFormat::Wav => wav_duration(data),
+      _ => None,
+    }
+  }
+}
+
+/// Checks that `file` is stored in `format`, read from its leading bytes.
+/// The SWF signature check is skipped with `--skip-swf-signature-check`, missing files are reported by the scan.
+pub async fn validate_sound_format(
+  resource: &str,
+  file: &Path,
+  format: SoundFormat,
+  errors: &mut ValidationErrors,
+) -> Result<()> {
+  if format == SoundFormat::Swf && cli::args().skip_swf_signature_check {
+    return Ok(());
+  }
+
+  // Long enough for every format that can be detected
+  let Ok(header) = files::read_header(file, 12).await else {
+    return Ok(());
+  };
+  let detected = SoundFormat::detect(&header);
+  if detected != Some(format) {
+    let expected = match format {
+      SoundFormat::Swf => "an SWF file (FWS, CWS or ZWS signature)".to_owned(),
+      format => format!("a {} file", format.extension()),
+    };
+    errors.push(ValidationError {
+      resource: resource.to_owned(),
+      prop: None,
+      message: format!(
+        "{} is not {}, detected {} format",
+        file.display(),
+        expected,
+        detected.map(|format| format.extension()).unwrap_or("unknown")
+      ),
+      file: Some(file.to_owned()),
+    })?;
+  }
+
+  Ok(())
+}
+
+fn wav_duration(data: &[u8]) -> Option<f32> {
+  let mut byte_rate = None;
+  let mut position = 12;
+  while position + 8 <= data.len() {
+    let id = &data[position..position + 4];
+    let size = u32::from_le_bytes(data[position + 4..position + 8].try_into().ok()?) as usize;
+    let body = position + 8;
+    match id {
+      b"fmt " if body + 12 <= data.len() => {
+        byte_rate = Some(u32::from_le_bytes(data[body + 8..body + 12].try_into().ok()?));
+      }
+      b"data" => return byte_rate.filter(|rate| *rate > 0).map(|rate| size as f32 / rate as f32),
+      _ => {}
+    }
+    position = body + size + size % 2;
+  }
+
+  None
 }
@@ -0,0 +1,311 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tara::TaraArchive;
+use walkdir::WalkDir;
+
+use super::{Resource, SoundFormat};
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{is_hidden_entry, read_file, ResourceInfo};
+use crate::{compression, files};
+
+const SOUND_EXTENSIONS: &[&str] = &["mp3", "ogg", "wav", "swf"];
+
+/// Packs a directory of sounds into a single `bank.tara` with an `index.json` entry
+/// mapping each file stem to its payload, so the client can load all of them at once.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SoundBankResource {
+  #[serde(skip_deserializing)]
+  #[schemars(skip)]
+  pub root: PathBuf,
+  #[serde(skip_deserializing)]
+  #[schemars(skip)]
+  pub info: Option<ResourceInfo>,
+  pub directory: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct SoundBankEntry {
+  /// Offset of the payload from the start of the archive data section.
+  offset: u64,
+  size: u64,
+  format: SoundFormat,
+  duration: Option<f32>,
+}
+
+#[async_trait]
+impl Resource for SoundBankResource {
+  fn init_root(&mut self, root: PathBuf) {
+    self.root = root;
+  }
+
+  async fn init(&mut self, info: ResourceInfo) -> Result<()> {
+    self.info = Some(info);
+    Ok(())
+  }
+
+  fn get_root(&self) -> PathBuf {
+    self.root.clone()
+  }
+
+  fn get_info(&self) -> &Option<ResourceInfo> {
+    &self.info
+  }
+
+  async fn input_files(&self) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(self.get_directory())
+      .max_depth(1)
+      .into_iter()
+      .filter_entry(|entry| !is_hidden_entry(entry))
+    {
+      let entry = entry?;
+      if !entry.file_type().is_file() {
+        continue;
+      }
+
+      let extension = entry
+        .path()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+      if let Some(extension) = extension {
+        if SOUND_EXTENSIONS.contains(&extension.as_str()) {
+          files.push(entry.into_path());
+        }
+      }
+    }
+    files.sort();
+
+    Ok(files)
+  }
+
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    let info = self.info.as_ref().unwrap();
+    let mut errors = ValidationErrors::default();
+    let error = |message: String, file: &Path| ValidationError {
+      resource: info.name.clone(),
+      prop: None,
+      message,
+      file: Some(file.to_owned()),
+    };
+
+    let mut names = BTreeMap::<String, Vec<PathBuf>>::new();
+    for file in self.input_files().await? {
+      match entry_names(&file) {
+        Ok((name, _)) => names.entry(name).or_default().push(file.clone()),
+        Err(name_error) => {
+          errors.push(error(format!("{:#}", name_error), &file))?;
+          continue;
+        }
+      }
+
+      // Long enough for every format that can be detected
+      let header = files::read_header(&file, 12)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?;
+      if SoundFormat::detect(&header).is_none() {
+        errors.push(error(
+          format!("{} is not a recognized sound file", file.display()),
+          &file,
+        ))?;
+      }
+    }
+
+    for (name, files) in names {
+      if files.len() > 1 {
+        let files = files.iter().map(|file| file.display().to_string()).collect::<Vec<_>>();
+        errors.push(error(
+          format!("duplicate sound {} in bank: {}", name, files.join(", ")),
+          &self.get_directory(),
+        ))?;
+      }
+    }
+
+    Ok(errors.into_vec())
+  }
+
+  async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
+    let (index, entries) = self.pack().await?;
+
+    let mut archive = TaraArchive::new();
+    for (name, data) in entries {
+      archive.add_entry(name, data);
+    }
+    archive.add_entry("index.json".to_owned(), serde_json::to_vec_pretty(&index)?);
+
+    let mut data = Vec::new();
+    archive.write(&mut data)?;
+    let data = compression::compress_archive(&self.info.as_ref().unwrap().name, data)?;
+
+    Ok(HashMap::from([("bank.tara".to_owned(), data)]))
+  }
+}
+
+impl SoundBankResource {
+  pub fn get_directory(&self) -> PathBuf {
+    self
+      .directory
+      .clone()
+      .map(|directory| {
+        if directory.starts_with(&self.root) {
+          directory
+        } else {
+          self.get_root().join(directory)
+        }
+      })
+      .unwrap_or_else(|| self.get_root())
+  }
+
+  /// Reads the sounds into archive entries, indexed by file stem in archive order.
+  async fn pack(&self) -> Result<(BTreeMap<String, SoundBankEntry>, Vec<(String, Vec<u8>)>)> {
+    let mut index = BTreeMap::new();
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    for file in self.input_files().await? {
+      let data = read_file(&file).await?;
+      let Some(format) = SoundFormat::detect(&data) else {
+        bail!("{} is not a recognized sound file", file.display());
+      };
+
+      let (name, file_name) = entry_names(&file)?;
+      let entry = SoundBankEntry {
+        offset,
+        size: data.len() as u64,
+        format,
+        duration: format.duration(&data),
+      };
+      if index.insert(name.clone(), entry).is_some() {
+        bail!("duplicate sound {} in bank {}", name, self.get_directory().display());
+      }
+
+      offset += data.len() as u64;
+      entries.push((file_name, data));
+    }
+    Ok((index, entries))
+  }
+}
+
+/// Returns the file stem a sound is indexed by and the file name it is archived as.
+fn entry_names(file: &Path) -> Result<(String, String)> {
+  let name = |name: Option<&OsStr>| {
+    name
+      .and_then(|name| name.to_str())
+      .map(str::to_owned)
+      .with_context(|| format!("{} has a non UTF-8 name", file.display()))
+  };
+  Ok((name(file.file_stem())?, name(file.file_name())?))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::cli;
+
+  fn bank(root: &Path) -> SoundBankResource {
+    SoundBankResource {
+      root: root.to_path_buf(),
+      info: Some(ResourceInfo::for_test("sounds.bank", 1, 1)),
+      directory: None,
+    }
+  }
+
+  fn write_files(root: &Path, files: &[(&str, &str)]) {
+    for (file, data) in files {
+      std::fs::write(root.join(file), data).unwrap();
+    }
+  }
+
+  #[tokio::test]
+  async fn index_has_offsets_and_sizes_in_archive_order() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[("b.ogg", "OggS-b"), ("a.mp3", "ID3-aaa")]);
+
+    let (index, entries) = bank(root.path()).pack().await.unwrap();
+
+    let names = entries.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["a.mp3", "b.ogg"]);
+    assert_eq!(
+      (index["a"].offset, index["a"].size, index["a"].format),
+      (0, 7, SoundFormat::Mp3)
+    );
+    assert_eq!(
+      (index["b"].offset, index["b"].size, index["b"].format),
+      (7, 6, SoundFormat::Ogg)
+    );
+  }
+
+  #[tokio::test]
+  async fn duplicate_stems_are_reported() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[("a.mp3", "ID3-a"), ("a.ogg", "OggS-a")]);
+    let resource = bank(root.path());
+
+    let errors = resource.validate().await.unwrap();
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(
+      errors[0].message.starts_with("duplicate sound a in bank:"),
+      "{}",
+      errors[0]
+    );
+    assert!(resource.pack().await.is_err());
+  }
+
+  #[tokio::test]
+  async fn unrecognized_files_are_reported() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[("a.mp3", "ID3-a"), ("broken.wav", "not a sound")]);
+
+    let errors = bank(root.path()).validate().await.unwrap();
+
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert!(
+      errors[0].message.ends_with("broken.wav is not a recognized sound file"),
+      "{}",
+      errors[0]
+    );
+    assert_eq!(errors[0].file, Some(root.path().join("broken.wav")));
+  }
+
+  #[tokio::test]
+  async fn hidden_and_other_files_are_skipped() {
+    let root = tempfile::tempdir().unwrap();
+    write_files(root.path(), &[
+      ("a.mp3", "ID3-a"),
+      (".a.mp3", "ID3-hidden"),
+      ("._b.mp3", "resource fork"),
+      ("notes.txt", "notes"),
+    ]);
+
+    let files = bank(root.path()).input_files().await.unwrap();
+
+    assert_eq!(files, [root.path().join("a.mp3")]);
+  }
+}
@@ -17,21 +17,24 @@
  */
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::diagnostics::{ValidationError, ValidationErrors};
+use crate::kind::{copy_output_file, read_file, validate_sound_format, ResourceInfo, SoundFormat};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SwfLibraryResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub file: Option<PathBuf>,
 }
@@ -59,12 +62,28 @@ impl Resource for SwfLibraryResource {
     Ok(vec![self.get_library()])
   }
 
+  async fn validate(&self) -> Result<Vec<ValidationError>> {
+    let mut errors = ValidationErrors::default();
+    validate_sound_format(
+      &self.info.as_ref().unwrap().name,
+      &self.get_library(),
+      SoundFormat::Swf,
+      &mut errors,
+    )
+    .await?;
+    Ok(errors.into_vec())
+  }
+
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
     Ok(HashMap::from([(
       "library.swf".to_owned(),
-      fs::read(self.get_library()).await.unwrap(),
+      read_file(self.get_library()).await?,
     )]))
   }
+
+  async fn write_outputs(&self, dir: &Path) -> Result<Vec<String>> {
+    copy_output_file(&self.get_library(), dir, "library.swf").await
+  }
 }
 
 impl SwfLibraryResource {
@@ -17,23 +17,43 @@
  */
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::fs;
+use tara::TaraArchive;
+use tracing::debug;
 
 use super::Resource;
-use crate::kind::ResourceInfo;
+use crate::imaging::TextureFormat;
+use crate::kind::{copy_output_file, read_file, write_output_files, ImageMetadata, MetadataDetails, ResourceInfo};
+use crate::{cli, compression, imaging};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct TextureResource {
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub root: PathBuf,
   #[serde(skip_deserializing)]
+  #[schemars(skip)]
   pub info: Option<ResourceInfo>,
   pub diffuse: Option<PathBuf>,
+  /// Grayscale alpha mask shipped instead of a diffuse image, for effects and UI masks without color.
+  /// Color images are converted to luminance.
+  pub mask: Option<PathBuf>,
+  /// Grayscale alpha of the diffuse image. With it, the texture is shipped as `image.tara`
+  /// with `a` (alpha) and `i` (diffuse) entries like multiframe textures instead of `image.tnk`.
+  pub alpha: Option<PathBuf>,
+  /// Re-encode JPEG images so pixel-identical re-exports keep the same version,
+  /// defaults to `--normalize-jpeg`.
+  pub normalize_jpeg: Option<bool>,
+  /// Downscale the image to fit within `[width, height]`, keeping its aspect ratio.
+  pub max_size: Option<[u32; 2]>,
+  /// Format to ship the texture in, the source is transcoded if it differs.
+  /// The source is shipped as is if not set.
+  pub format: Option<TextureFormat>,
 }
 
 #[async_trait]
@@ -43,6 +63,13 @@ impl Resource for TextureResource {
   }
 
   async fn init(&mut self, info: ResourceInfo) -> Result<()> {
+    if self.diffuse.is_some() && self.mask.is_some() {
+      bail!("texture {} sets both diffuse and mask", info.name);
+    }
+    if self.mask.is_some() && self.alpha.is_some() {
+      bail!("texture {} sets both mask and alpha", info.name);
+    }
+
     self.info = Some(info);
     Ok(())
   }
@@ -56,14 +83,48 @@ impl Resource for TextureResource {
   }
 
   async fn input_files(&self) -> Result<Vec<PathBuf>> {
-    Ok(vec![self.get_diffuse()])
+    let mut files = vec![self.get_source()];
+    files.extend(self.get_alpha());
+    Ok(files)
+  }
+
+  async fn version_data(&self, file: &Path) -> Result<Option<Vec<u8>>> {
+    if self.reencodes() && file == self.get_source() {
+      return Ok(Some(self.read_image().await?));
+    }
+    Ok(None)
   }
 
   async fn output_files(&self) -> Result<HashMap<String, Vec<u8>>> {
-    Ok(HashMap::from([(
-      "image.tnk".to_owned(),
-      fs::read(self.get_diffuse()).await.unwrap(),
-    )]))
+    let Some(alpha) = self.get_alpha() else {
+      return Ok(HashMap::from([("image.tnk".to_owned(), self.read_image().await?)]));
+    };
+
+    // Same order as multiframe textures: a, i
+    let mut archive = TaraArchive::new();
+    archive.add_entry("a".to_owned(), read_file(alpha).await?);
+    archive.add_entry("i".to_owned(), self.read_image().await?);
+    let mut data = Vec::new();
+    archive.write(&mut data)?;
+    let data = compression::compress_archive(&self.info.as_ref().unwrap().name, data)?;
+
+    Ok(HashMap::from([("image.tara".to_owned(), data)]))
+  }
+
+  async fn write_outputs(&self, dir: &Path) -> Result<Vec<String>> {
+    if self.mask.is_some() || self.alpha.is_some() || self.reencodes() {
+      return write_output_files(dir, self.output_files().await?).await;
+    }
+    copy_output_file(&self.get_source(), dir, "image.tnk").await
+  }
+
+  async fn metadata(&self) -> Result<Option<MetadataDetails>> {
+    // Resizing changes the dimensions of the output
+    let metadata = match self.max_size {
+      Some(_) => ImageMetadata::from_data(&self.read_image().await?)?,
+      None => ImageMetadata::probe(&self.get_source()).await?,
+    };
+    Ok(Some(MetadataDetails::Image(metadata)))
   }
 }
 
@@ -81,4 +142,78 @@ impl TextureResource {
       })
       .unwrap_or_else(|| self.get_root().join("diffuse.jpg"))
   }
+
+  pub fn get_mask(&self) -> Option<PathBuf> {
+    self.mask.clone().map(|file| {
+      if file.starts_with(&self.root) {
+        file
+      } else {
+        self.get_root().join(file)
+      }
+    })
+  }
+
+  pub fn get_alpha(&self) -> Option<PathBuf> {
+    self.alpha.clone().map(|file| {
+      if file.starts_with(&self.root) {
+        file
+      } else {
+        self.get_root().join(file)
+      }
+    })
+  }
+
+  /// Returns the mask if set, the diffuse image otherwise.
+  pub fn get_source(&self) -> PathBuf {
+    self.get_mask().unwrap_or_else(|| self.get_diffuse())
+  }
+
+  fn normalizes_jpeg(&self) -> bool {
+    self.normalize_jpeg.unwrap_or(cli::args().normalize_jpeg)
+  }
+
+  /// Whether the source may be encoded again instead of being shipped as is.
+  fn reencodes(&self) -> bool {
+    self.normalizes_jpeg() || self.format.is_some() || self.max_size.is_some()
+  }
+
+  async fn read_image(&self) -> Result<Vec<u8>> {
+    let mut data = read_file(self.get_source()).await?;
+    if self.mask.is_some() {
+      let (mask, converted) = imaging::to_mask(data)?;
+      data = mask;
+      if converted {
+        debug!("converted mask of {} to grayscale", self.info.as_ref().unwrap().name);
+      }
+    }
+
+    // Resized and transcoded images come from our own encoder already, so they are not normalized
+    let mut encoded = false;
+    if let Some(max_size) = self.max_size {
+      let (resized, changed) = imaging::fit(data, max_size)?;
+      data = resized;
+      if changed {
+        debug!("downscaled {} to fit {:?}", self.info.as_ref().unwrap().name, max_size);
+        encoded = true;
+      }
+    }
+
+    if let Some(format) = self.format {
+      let (converted, changed) = imaging::convert(data, format)?;
+      data = converted;
+      if changed {
+        debug!("transcoded {} to {:?}", self.info.as_ref().unwrap().name, format);
+        encoded = true;
+      }
+    }
+    if encoded {
+      return Ok(data);
+    }
+
+    if self.normalizes_jpeg() {
+      imaging::normalize_jpeg(data)
+    } else {
+      Ok(data)
+    }
+  }
 }
@@ -16,30 +16,51 @@
  * along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+mod cli;
+mod compression;
+mod diagnostics;
+mod files;
+mod imaging;
 mod kind;
+mod progress;
+mod version;
 
-use std::collections::{HashMap, HashSet};
-use std::io::stdout;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{self, stdout, Cursor};
+use std::iter;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Instant, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use crc::{Crc, CRC_32_ISO_HDLC};
-use threedee::{Editor, Main, Material, MaterialTextureMap};
+use futures::{stream, StreamExt};
+use glob::{MatchOptions, Pattern};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use threedee::{Editor, Main, Material, MaterialTextureMap, Parser3DS};
 use tokio::fs;
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tracing::{debug, error, info, trace, warn};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
 use walkdir::WalkDir;
 
+use self::cli::{ArchiveCompression, CacheMode, Command, LogFormat, ManifestFormat, OutputLayout};
+use self::diagnostics::{Severity, ValidationError, ValidationErrors};
+use self::imaging::ImageRole;
 use self::kind::ResourceDefinition;
+use self::version::{VersionDigest, VersionOrder};
 use crate::kind::{
-  GameObjectResource, ImageResource, MapResource, Resource, ResourceInfo, SoundResource, SwfLibraryResource,
-  TextureResource,
+  parse_properties_file, read_file, read_map, ChunkIndex, DependencyOutput, GameObjectResource, ImageResource,
+  LocalizedImageResource, MapResource, MultiframeTextureLayout, MultiframeTextureResource, Object3DImage,
+  Object3DResource, ProplibResource, RawDataFile, RawDataResource, Resource, ResourceInfo, ResourceKind,
+  ResourceMetadata, ResourceProperties, SoundFormat, SoundResource, SwfLibraryResource, TextureResource,
 };
 
 fn is_path_hidden<P: AsRef<Path>>(path: P) -> bool {
@@ -66,48 +87,65 @@ fn preprocess_input_files<P: AsRef<Path>>(paths: &[P]) -> Result<Vec<&Path>> {
 }
 
 pub static RESOURCE_DEFINITION_FILE: &str = "resource.yaml";
+/// Lists name patterns of resources to skip, one per line, at the input root.
+pub static RESOURCE_IGNORE_FILE: &str = ".resourceignore";
 pub static CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-  let console = tracing_subscriber::fmt::layer()
-    .with_writer(Arc::new(stdout()))
-    .and_then(EnvFilter::from_default_env());
+  let args = cli::init();
+  files::set_max_open_files(args.max_open_files);
+
+  let console = match args.log_format {
+    LogFormat::Text => {
+      // Printed output of `--explain` and `--dry-run` would be overdrawn by the bar
+      if !args.explain && !args.dry_run && !args.quiet {
+        progress::enable();
+      }
+      tracing_subscriber::fmt::layer()
+        .with_writer(progress::LogWriter)
+        .and_then(log_filter())
+        .boxed()
+    }
+    LogFormat::Json => tracing_subscriber::fmt::layer()
+      .json()
+      .with_writer(Arc::new(stdout()))
+      .and_then(log_filter())
+      .boxed(),
+  };
   tracing_subscriber::registry().with(console).init();
+  if let Some(file) = &args.emit_schema {
+    return emit_schema(file).await;
+  }
   info!("Hello, world!");
 
-  let out = Path::new("out");
-  let root = Path::new("resources");
-
-  if !out.try_exists().unwrap() {
-    fs::create_dir_all(out).await.unwrap();
-  }
+  let out = args.output.as_path();
+  let root = args.input.as_path();
+  validate_output_dir(out, root)?;
 
-  let mtimes_file = out.join("mtimes");
-  let mut resource_cached_mtimes = HashMap::new();
+  let mtimes_file = args.mtimes_file.clone().unwrap_or_else(|| out.join("mtimes"));
   let mut resource_actual_mtimes = HashMap::new();
   let mut unchanged_resources = HashSet::new();
+  let mut triggers = HashMap::new();
+  // Input files of every resource, relative to the resources root
+  let mut resource_inputs = HashMap::new();
+  let mut explanations = HashMap::new();
+  let mut sources = HashMap::new();
 
   let mut mtime_skip_files = 0;
   let mut input_files = 0;
   let mut output_files = 0;
   let start = Instant::now();
 
-  if mtimes_file.try_exists().unwrap() {
+  let (resource_cached_mtimes, resource_cached_hashes) = if mtimes_file.try_exists().unwrap() {
     info!("loading resource mtimes...");
-    for entry in fs::read_to_string(&mtimes_file).await.unwrap().split('\n') {
-      let entry = entry.trim();
-      if let Some((file, time)) = entry.split_once(": ") {
-        let time = time.parse::<u128>().unwrap();
-
-        debug!("{}: {}", file, time);
-        resource_cached_mtimes.insert(file.to_owned(), time);
-      }
-    }
-  }
+    read_mtimes(&mtimes_file).await
+  } else {
+    Default::default()
+  };
 
   info!("scanning resources...");
-  let mut resources = Vec::new();
+  let mut candidates = Vec::new();
   for entry in WalkDir::new(root) {
     let entry = entry.unwrap();
     let path = entry.path();
@@ -117,393 +155,595 @@ async fn main() -> Result<()> {
       continue;
     }
 
-    let namespaces = get_namespaces(path).await;
-
-    // Read full definitions
-    if path.is_dir() {
-      let definition_path = path.join(RESOURCE_DEFINITION_FILE);
-      if !definition_path.try_exists().unwrap() {
-        continue;
-      }
-
-      let definition = fs::read_to_string(&definition_path).await.unwrap();
-      let mut definition: ResourceDefinition = serde_yaml::from_str(&definition)
-        .unwrap_or_else(|error| panic!("failed to read definition {}: {error}", definition_path.display()));
-      definition.resource_mut().init_root(path.to_path_buf());
-
-      let name = path
-        .strip_prefix(root)?
-        .components()
-        .map(|component| component.as_os_str().to_str().unwrap())
-        .filter(|component| !component.starts_with("@"))
-        .collect::<Vec<_>>()
-        .join(".");
-      let mut id = CRC.checksum(path.to_string_lossy().to_string().as_bytes());
-      if let ResourceDefinition::Object3D(resource) = &definition {
-        if let Some(forced_id) = resource.id {
-          id = forced_id;
+    if path.is_dir() && path.join(RESOURCE_DEFINITION_FILE).try_exists().unwrap() {
+      candidates.push(Candidate::Full(path.to_path_buf()));
+    } else if path.is_dir() && parse_short_name(path).is_some_and(|(_, kind, _)| SHORT_DIRECTORY_KINDS.contains(&kind))
+    {
+      candidates.push(Candidate::Short(path.to_path_buf()));
+    }
+    if let Some((_, kind, _)) = parse_short_name(path).filter(|_| path.is_file()) {
+      if SHORT_KINDS.contains(&kind) {
+        candidates.push(Candidate::Short(path.to_path_buf()));
+      } else {
+        // A mistyped kind shouldn't abort the whole scan
+        let message = format!(
+          "{}: unknown short definition kind {:?}, expected one of {}",
+          path.display(),
+          kind,
+          SHORT_KINDS.join(", ")
+        );
+        if args.strict {
+          bail!(message);
         }
+        diagnostics::warning(message);
       }
-      debug!(?name, ?id, ?namespaces, "resource");
-
-      let mut raw_input_files = definition.resource().input_files().await?;
-      raw_input_files.push(definition_path.clone());
-      let preprocessed_input_files = preprocess_input_files(&raw_input_files)?;
-
-      let mtime_input_files = preprocessed_input_files.clone();
-
-      let mut changed = false;
-      for file in &mtime_input_files {
-        if file.is_dir() {
-          continue;
-        }
-
-        let cache_path = file.strip_prefix(root).unwrap().to_str().unwrap();
+    }
+  }
 
-        let actual_mtime = fs::metadata(file)
-          .await
-          .unwrap()
-          .modified()
-          .map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_millis())
-          .expect("unsupported platform");
-        resource_actual_mtimes.insert(cache_path.to_owned(), actual_mtime);
-
-        if let Some(cached_mtime) = resource_cached_mtimes.get(cache_path) {
-          if actual_mtime == *cached_mtime {
-            debug!("{} has not changed", file.display());
-            continue;
-          }
+  // Excluded before scanning, so a broken definition can be skipped
+  let exclude = read_exclude_patterns(root).await?;
+  if !exclude.is_empty() {
+    let (included, excluded) = exclude_candidates(candidates, root, &exclude)?;
+    if !excluded.is_empty() {
+      // Not a diagnostic: excluding is asked for, and must not fail `--deny-warnings`
+      warn!("excluding {} resources: {}", excluded.len(), excluded.join(", "));
+    }
+    candidates = included;
+  }
 
-          debug!("{} has changed", file.display());
-          changed = true;
-        } else {
-          debug!("new file {}", file.display());
-          changed = true;
-        }
+  let imported_cache = match &args.import_cache {
+    Some(file) => {
+      info!("importing content cache from {}...", file.display());
+      let cache = fs::read(file)
+        .await
+        .with_context(|| format!("failed to read content cache {}", file.display()))?;
+      let cache: ContentCache =
+        serde_json::from_slice(&cache).with_context(|| format!("failed to read content cache {}", file.display()))?;
+      Some(cache.files)
+    }
+    None => None,
+  };
+  let mut content_hashes = BTreeMap::new();
+  // An imported cache takes precedence over the hashes of the previous local run
+  let cached_hashes = match (&imported_cache, args.cache_mode) {
+    (Some(cache), _) => Some(cache),
+    (None, CacheMode::Hash) => Some(&resource_cached_hashes),
+    (None, CacheMode::Mtime) => None,
+  };
+
+  let scanned = stream::iter(&candidates)
+    .map(|candidate| scan_resource(candidate, root, &resource_cached_mtimes, cached_hashes))
+    .buffer_unordered(args.scan_jobs.max(1))
+    .collect::<Vec<_>>()
+    .await;
+
+  let checking = matches!(args.command, Some(Command::Check));
+  let mut resources = Vec::new();
+  let mut scan_errors = Vec::new();
+  for resource in scanned {
+    let resource = match resource {
+      Ok(resource) => resource,
+      Err(error) if checking => {
+        diagnostics::error(format!("{:#}", error));
+        continue;
       }
-
-      if name.contains("localization") {
-        warn!("regenerate localization {}", name);
-      } else if !changed {
-        debug!("skipping {} as no files have been changed", name);
-        mtime_skip_files += 1;
-        unchanged_resources.insert(id as i64);
-        // continue;
+      // Every broken definition is reported before failing
+      Err(error) => {
+        scan_errors.push(error);
+        continue;
       }
+    };
 
-      let mut digest = CRC.digest();
-      for file in &preprocessed_input_files {
-        if file.is_dir() {
-          continue;
-        }
-
-        trace!("using {} to calculate version for {}", file.display(), id);
-        digest.update(&fs::read(file).await.unwrap());
-        input_files += 1;
-      }
-      let version = digest.finalize();
-
-      definition
-        .resource_mut()
-        .init(ResourceInfo {
-          name: name.clone(),
-          id: id as i64,
-          version: version as i64,
-          namespaces: namespaces.clone(),
-        })
-        .await?;
-      debug!(
-        "read resource definition {}: {:?}",
-        definition_path.display(),
-        definition
-      );
-
-      resources.push(definition);
-    }
-
-    // Read short definitions
-    if path.is_file() {
-      let file_name = path.file_name().unwrap().to_str().unwrap();
-      let (file_name, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
-      if let Some((name, kind)) = file_name.rsplit_once('@') {
-        debug!(?name, ?kind, ?extension, "discovered short resource");
-
-        let mut definition = match kind {
-          "Sound" => ResourceDefinition::Sound(SoundResource {
-            root: Default::default(),
-            info: None,
-            sound: Some(path.to_path_buf()),
-          }),
-          "Map" => ResourceDefinition::Map(MapResource {
-            root: Default::default(),
-            info: None,
-            map: Some(path.to_path_buf()),
-            parsed: None,
-            proplibs: Default::default(),
-            namespace: None,
-          }),
-          "Proplib" => unimplemented!("use full resource definition"),
-          "Texture" => ResourceDefinition::Texture(TextureResource {
-            root: Default::default(),
-            info: None,
-            diffuse: Some(path.to_path_buf()),
-          }),
-          "Image" => ResourceDefinition::Image(ImageResource {
-            root: Default::default(),
-            info: None,
-            image: Some(path.to_path_buf()),
-          }),
-          "MultiframeTexture" => unimplemented!("use full resource definition"),
-          "LocalizedImage" => unimplemented!("use full resource definition"),
-          "Object3D" => unimplemented!("use full resource definition"),
-          "SwfLibrary" => ResourceDefinition::SwfLibrary(SwfLibraryResource {
-            root: Default::default(),
-            info: None,
-            file: Some(path.to_path_buf()),
-          }),
-          "GameObject" => ResourceDefinition::GameObject(GameObjectResource {
-            root: Default::default(),
-            info: None,
-            file: Some(path.to_path_buf()),
-          }),
-          _ => unimplemented!("{} is not implemented", kind),
-        };
-        definition
-          .resource_mut()
-          .init_root(path.parent().unwrap().to_path_buf());
-
-        let name = path
-          .strip_prefix(root)?
-          .parent()
-          .unwrap()
-          .components()
-          .map(|component| component.as_os_str().to_str().unwrap())
-          .filter(|component| !component.starts_with("@"))
-          .collect::<Vec<_>>()
-          .join(".")
-          + "."
-          + name;
-        let id = CRC.checksum(path.to_string_lossy().to_string().as_bytes());
-        debug!(?name, ?id, ?namespaces, "resource");
-
-        let mut raw_input_files = definition.resource().input_files().await?;
-        raw_input_files.push(path.to_owned());
-        let preprocessed_input_files = preprocess_input_files(&raw_input_files)?;
-
-        let mtime_input_files = preprocessed_input_files.clone();
-
-        let mut changed = false;
-        for file in &mtime_input_files {
-          if file.is_dir() {
-            continue;
-          }
-
-          let cache_path = file.strip_prefix(root).unwrap().to_str().unwrap();
-
-          let actual_mtime = fs::metadata(file)
-            .await
-            .unwrap()
-            .modified()
-            .map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_millis())
-            .expect("unsupported platform");
-          resource_actual_mtimes.insert(cache_path.to_owned(), actual_mtime);
-
-          if let Some(cached_mtime) = resource_cached_mtimes.get(cache_path) {
-            if actual_mtime == *cached_mtime {
-              debug!("{} has not changed", file.display());
-              continue;
-            }
-
-            debug!("{} has changed", file.display());
-            changed = true;
-          } else {
-            debug!("new file {}", file.display());
-            changed = true;
-          }
-        }
-
-        if !changed {
-          debug!("skipping {} as no files have been changed", name);
-          mtime_skip_files += 1;
-          unchanged_resources.insert(id as i64);
-          // continue;
-        }
-
-        let mut digest = CRC.digest();
-        for file in &preprocessed_input_files {
-          if file.is_dir() {
-            continue;
-          }
-
-          trace!("using {} to calculate version for {}", file.display(), name);
-          digest.update(&fs::read(file).await.unwrap());
-          input_files += 1;
+    if checking {
+      let name = &resource.definition.resource().get_info().as_ref().unwrap().name;
+      for file in &resource.missing_files {
+        if file_exists_case_insensitive(file).is_none() {
+          diagnostics::error(format!("resource {}: input file {}", name, missing_file_message(file)));
         }
-        let version = digest.finalize();
-
-        definition
-          .resource_mut()
-          .init(ResourceInfo {
-            name: name.clone(),
-            id: id as i64,
-            version: version as i64,
-            namespaces: namespaces.clone(),
-          })
-          .await?;
-        debug!("read short resource definition {}: {:?}", path.display(), definition);
-
-        resources.push(definition);
       }
     }
+
+    let info = resource.definition.resource().get_info().as_ref().unwrap();
+    if resource.changed {
+      triggers.insert(info.name.clone(), resource.triggered_by);
+      explanations.insert(info.name.clone(), resource.reasons);
+    } else {
+      mtime_skip_files += 1;
+      unchanged_resources.insert(info.id);
+    }
+    input_files += resource.input_files;
+    resource_inputs.insert(info.name.clone(), resource.mtimes.keys().cloned().collect::<Vec<_>>());
+    resource_actual_mtimes.extend(resource.mtimes);
+    content_hashes.extend(resource.hashes);
+    sources.insert(info.name.clone(), resource.source);
+    resources.push(resource.definition);
+  }
+  if !scan_errors.is_empty() {
+    for error in &scan_errors {
+      error!("{:#}", error);
+    }
+    bail!("{} resources failed to load", scan_errors.len());
   }
+  // Scans finish in arbitrary order
+  resources.sort_by_cached_key(|definition| {
+    let info = definition.resource().get_info().as_ref().unwrap();
+    (info.name.clone(), info.id)
+  });
 
-  let mut proplibs = resources
+  // Proplibs parse their library.xml and images.xml in init
+  let proplibs = resources
     .iter()
     .filter(|resource| matches!(resource, ResourceDefinition::Proplib(_)))
     .cloned()
     .collect::<Vec<_>>();
+  let mut validation_errors = ValidationErrors::default();
 
-  info!("validating proplibs...");
-  for definition in &mut proplibs {
-    if let ResourceDefinition::Proplib(resource) = definition {
-      let root = resource.get_root();
+  info!("discovered {} resources", resources.len());
 
-      for entry in WalkDir::new(resource.get_root()) {
-        let entry = entry?;
-        if entry.file_type().is_dir() {
-          continue;
-        }
-        if entry.file_name() == "library.xml" {
-          debug!("found library.xml for {}", resource.get_info().as_ref().unwrap().name);
-          let content = fs::read_to_string(entry.path()).await.unwrap();
-          let deserializer = &mut quick_xml::de::Deserializer::from_str(&content);
-          resource.library = Some(serde_path_to_error::deserialize(deserializer)?);
-        }
-        if entry.file_name() == "images.xml" {
-          debug!("found images.xml for {}", resource.get_info().as_ref().unwrap().name);
-          let content = fs::read_to_string(entry.path()).await.unwrap();
-          let deserializer = &mut quick_xml::de::Deserializer::from_str(&content);
-          resource.images = Some(serde_path_to_error::deserialize(deserializer)?);
-        }
-      }
+  if args.list {
+    print_resource_list(&resources);
+    return Ok(());
+  }
 
-      if let Some(images) = &resource.images {
-        for image in &images.images {
-          trace!("{:?}", image);
+  let mut build_list = match &args.build_list {
+    Some(file) => {
+      let selected = read_build_list(file, &resources).await?;
+      info!(
+        "building {} of {} resources from {}",
+        selected.len(),
+        resources.len(),
+        file.display()
+      );
+      Some(selected)
+    }
+    None => None,
+  };
+  if !args.only.is_empty() {
+    let matched = select_matching_resources(&args.only, &resources)?;
+    // Combined with a build list, only the resources selected by both are generated
+    let selected = match build_list {
+      Some(listed) => listed.intersection(&matched).cloned().collect(),
+      None => matched,
+    };
+    info!(
+      "building {} of {} resources matching {}",
+      selected.len(),
+      resources.len(),
+      args.only.join(", ")
+    );
+    build_list = Some(selected);
+  }
+  if !args.namespace.is_empty() {
+    let matched = select_namespace_resources(&args.namespace, &resources);
+    // Intersected like `--only`, so both narrow down the selection
+    let selected = match build_list {
+      Some(listed) => listed.intersection(&matched).cloned().collect(),
+      None => matched,
+    };
+    let namespaces = args
+      .namespace
+      .iter()
+      .map(|(key, value)| format!("{}={}", key, value))
+      .collect::<Vec<_>>();
+    info!(
+      "building {} of {} resources with namespaces {}",
+      selected.len(),
+      resources.len(),
+      namespaces.join(", ")
+    );
+    build_list = Some(selected);
+  }
 
-          let file = root.join(&image.diffuse);
-          let file = file_exists_case_insensitive(&file);
-          if let Some(_file) = &file {
-          } else {
-            error!("proplib: {:?}", resource.get_info());
-            panic!("diffuse file {:?} for texture {} not exists", file, image.name);
-          }
+  // Collisions with resources outside of the selection still break the build
+  let mut collisions = find_id_collisions(&resources, &sources);
+  if checking {
+    if let Some(selected) = &build_list {
+      // Only check the selection, proplibs are kept whole above so its maps still resolve them
+      resources.retain(|definition| selected.contains(&definition.resource().get_info().as_ref().unwrap().name));
+      collisions.retain(|(_, names)| names.iter().any(|name| selected.contains(name)));
+    }
+  }
 
-          if let Some(alpha) = &image.alpha {
-            let file = root.join(alpha);
-            let file = file_exists_case_insensitive(&file);
-            if let Some(_file) = &file {
-            } else {
-              panic!("alpha file {:?} for texture {} not exists", file, image.name);
-            }
-          }
-        }
+  // Colliding resources would overwrite each other's output directory
+  if !checking {
+    for (id, names) in &collisions {
+      let message = format!("resource id {} is used by {}", id, names.join(", "));
+      if args.allow_id_collisions {
+        diagnostics::warning(message);
+      } else {
+        error!("{}", message);
       }
-
-      // let library = resource.library.as_ref().unwrap();
-      // for group in &library.prop_groups {
-      //   for prop in &group.props {
-      //     if let Some(mesh) = &prop.mesh {
-      //       let mesh_file = root.join(&mesh.file);
-      //       let mesh_file = file_exists_case_insensitive(&mesh_file);
-      //       if let Some(mesh_file) = &mesh_file {
-      //         let data = fs::read(mesh_file).await.unwrap();
-      //         let mut data = Cursor::new(data.as_slice());
-      //         let mut parser = araumi_3ds::Parser3DS::new(&mut data);
-      //         let main = &parser.read_main()[0];
-      //         let default_texture = get_texture_map_name(&main);
-      //         if let Some(default_texture) = &default_texture {
-      //           let default_file = file_exists_case_insensitive(root.join(default_texture));
-      //           if let Some(default_file) = &default_file {
-      //             // info!("{:?}", default_file);
-      //           } else {
-      //             warn!("mesh {}/{}/{} ({:?}) default texture {} not exists", library.name, group.name, prop.name, mesh_file, default_texture);
-      //           }
-      //         } else {
-      //           panic!("mesh {}/{}/{} ({:?}) has no default texture map", library.name, group.name, prop.name, mesh_file);
-      //         }
-      //       } else {
-      //         panic!("mesh {}/{}/{} file {:?} not exists", library.name, group.name, prop.name, mesh_file);
-      //       }
-
-      //       // for texture in &mesh.textures {
-      //       //   info!("texture {:?}", texture);
-      //       // }
-      //     }
-      //   }
-      // }
-      // info!("{:?}", library);
-      // info!("{:?}", images);
-    } else {
-      unreachable!();
+    }
+    if !collisions.is_empty() && !args.allow_id_collisions {
+      bail!("{} resource ids are used by more than one resource", collisions.len());
     }
   }
-  // return Ok(());
 
-  info!("discovered {} resources", resources.len());
+  if args.validate_color_depth {
+    info!("validating image color depth...");
+    validate_color_depth(&resources).await;
+  }
 
-  {
-    debug!("writing mtimes file...");
-    let mut mtimes_file = File::create(mtimes_file).await.unwrap();
-    for (file, mtime) in resource_actual_mtimes {
-      mtimes_file
-        .write_all(format!("{}: {}\n", file, mtime).as_bytes())
-        .await
-        .unwrap();
+  if args.validate_images || args.strict {
+    info!("validating images...");
+    validate_images(&resources, &mut validation_errors).await?;
+  }
+
+  if checking {
+    for error in validation_errors.into_vec() {
+      diagnostics::error(error.to_string());
+    }
+    check(&mut resources, &proplibs, collisions).await;
+
+    let errors = diagnostics::count(Severity::Error);
+    let warnings = diagnostics::count(Severity::Warning);
+    info!("check completed: {} errors, {} warnings", errors, warnings);
+    if errors > 0 || (args.deny_warnings && warnings > 0) {
+      bail!("check failed with {} errors and {} warnings", errors, warnings);
+    }
+    return Ok(());
+  }
+
+  if let Some(selected) = &build_list {
+    // Keep the previous change detection state of skipped resources, so they are regenerated on the next run
+    for definition in &resources {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      if selected.contains(&info.name) {
+        continue;
+      }
+      for file in triggers.get(&info.name).into_iter().flatten() {
+        match resource_cached_mtimes.get(file) {
+          Some(mtime) => resource_actual_mtimes.insert(file.clone(), *mtime),
+          None => resource_actual_mtimes.remove(file),
+        };
+        match cached_hashes.and_then(|cache| cache.get(file)) {
+          Some(hash) => content_hashes.insert(file.clone(), *hash),
+          None => content_hashes.remove(file),
+        };
+      }
     }
-    mtimes_file.flush().await.unwrap();
   }
 
+  if !args.dry_run && !out.try_exists().unwrap() {
+    fs::create_dir_all(out).await.unwrap();
+  }
+
+  // Resources are sorted by name and dependencies are ordered deterministically,
+  // so the streamed manifest is deterministic too
+  let mut manifest = match args.manifest_format {
+    ManifestFormat::Json => None,
+    ManifestFormat::Ndjson if args.dry_run => None,
+    ManifestFormat::Ndjson => Some(BufWriter::new(File::create(out.join("00-resources.ndjson")).await?)),
+  };
+
   let mut processed_resources = 0;
-  for definition in &mut resources {
+  let mut outputs = HashMap::<String, DependencyOutput>::new();
+  let mut regenerated = HashSet::<String>::new();
+  let mut generated = Vec::new();
+  let mut timings = Vec::new();
+  progress::start(resources.len());
+  for index in generation_order(&resources)? {
+    let definition = &mut resources[index];
     let info = definition.resource().get_info().as_ref().unwrap();
-    if unchanged_resources.contains(&info.id) {
+    progress::advance(&info.name);
+    let depends_on = info.properties.depends_on.clone();
+    let dependency_regenerated = depends_on.iter().any(|name| regenerated.contains(name));
+    let path = out.join(&info.path);
+    if build_list
+      .as_ref()
+      .is_some_and(|selected| !selected.contains(&info.name))
+    {
+      trace!("skipping {} as it is not in the build list", info.name);
+      if path.exists() {
+        outputs.insert(
+          info.name.clone(),
+          DependencyOutput::new(info, list_output_files(&path), path),
+        );
+      }
+      append_manifest_line(&mut manifest, definition).await?;
+      continue;
+    }
+
+    // The output directory is missing if it was deleted or the output layout changed
+    if unchanged_resources.contains(&info.id) && !dependency_regenerated && path.exists() {
+      outputs.insert(
+        info.name.clone(),
+        DependencyOutput::new(info, list_output_files(&path), path),
+      );
+      append_manifest_line(&mut manifest, definition).await?;
       continue;
     }
 
+    // Carries the resource fields into events logged while generating it, for `--log-format json`
+    let span = info_span!("resource", name = %info.name, id = info.id, kind = definition.kind_name());
     if let ResourceDefinition::Map(resource) = definition {
       debug!("initializing map {:?}", resource.get_info().as_ref().unwrap());
-      resource.init_proplibs(&proplibs).await?;
-      resource.validate_props(&proplibs).await?;
+      resource.init_proplibs(&proplibs).instrument(span.clone()).await?;
+      span.in_scope(|| resource.validate_spawn_overlaps());
+    }
+    let errors = definition.resource().validate().instrument(span.clone()).await?;
+    if !errors.is_empty() {
+      validation_errors.extend(errors);
+      let name = &definition.resource().get_info().as_ref().unwrap().name;
+      forget_inputs(
+        resource_inputs.get(name).map(Vec::as_slice).unwrap_or_default(),
+        &mut resource_actual_mtimes,
+        &mut content_hashes,
+      );
+      continue;
     }
+    definition.resource_mut().init_dependencies(
+      depends_on
+        .iter()
+        .filter_map(|name| outputs.get(name).cloned())
+        .collect(),
+    );
 
     let info = definition.resource().get_info().as_ref().unwrap();
-    let path = out.join(info.encode());
-    // .join(info.id.to_string())
-    // .join(info.version.to_string());
-    if path.try_exists()? {
-      warn!(
-        "skipping {:?} ({}) as directory already exists, cache is probably corrupt",
-        info,
-        path.display()
-      );
-      // continue;
+    let triggered_by = triggers.remove(&info.name).unwrap_or_default();
+    if args.explain || args.dry_run {
+      let mut reasons = explanations.remove(&info.name).unwrap_or_default();
+      if dependency_regenerated {
+        let dependencies = depends_on
+          .iter()
+          .filter(|name| regenerated.contains(*name))
+          .map(String::as_str)
+          .collect::<Vec<_>>();
+        reasons.push(format!("dependency regenerated: {}", dependencies.join(", ")));
+      }
+      if unchanged_resources.contains(&info.id) && !dependency_regenerated {
+        reasons.push(format!("output directory {} is missing", path.display()));
+      }
+      println!("{} will be regenerated:", info.name);
+      for reason in &reasons {
+        println!("  {}", reason);
+      }
+    }
+    // Marked as regenerated without writing anything, so the resources depending on it are listed too
+    if args.dry_run {
+      println!("  would write {}", path.display());
+      regenerated.insert(info.name.clone());
+      continue;
     }
 
-    fs::create_dir_all(&path).await?;
+    // .join(info.id.to_string())
+    // .join(info.version.to_string());
+    // Parents share prefixes between resources, so only the resource directory itself
+    // is expected to be new. Checking for it up front would race with concurrent writers.
+    create_output_dir(&path, info).await?;
     processed_resources += 1;
 
-    info!("writing output files for {:?}", info);
-    debug!("writing output files for {:?}", definition);
-    for (name, data) in &definition.resource().output_files().await? {
-      fs::write(path.join(name), data).await?;
+    if !triggered_by.is_empty() {
+      info!("regenerating {}: triggered by {}", info.name, triggered_by.join(", "));
+    } else if dependency_regenerated {
+      info!("regenerating {}: dependency regenerated", info.name);
+    }
+    generated.push(GeneratedResource {
+      resource: info.name.clone(),
+      triggered_by,
+    });
+
+    span.in_scope(|| {
+      info!("writing output files for {:?}", info);
+      debug!("writing output files for {:?}", definition);
+    });
+    let started = Instant::now();
+    let mut written = definition
+      .resource()
+      .write_outputs(&path)
+      .instrument(span.clone())
+      .await?;
+    let elapsed = started.elapsed();
+    debug!("wrote outputs of {} in {:?}", info.name, elapsed);
+    if args.timings {
+      let mut bytes = 0;
+      for name in &written {
+        bytes += fs::metadata(path.join(name)).await?.len();
+      }
+      timings.push(ResourceTiming {
+        name: info.name.clone(),
+        kind: definition.kind_name(),
+        ms: elapsed.as_millis(),
+        bytes,
+      });
+    }
+    if args.verify {
+      verify_outputs(info, &written)?;
+    }
+    if args.meta {
+      if written.iter().any(|name| name == "meta.json") {
+        bail!("resource {} outputs meta.json more than once", info.name);
+      }
+      let metadata = ResourceMetadata {
+        kind: definition.kind_name(),
+        details: definition.resource().metadata().instrument(span.clone()).await?,
+      };
+      files::write(path.join("meta.json"), serde_json::to_vec_pretty(&metadata)?).await?;
+      written.push("meta.json".to_owned());
+    }
+    for name in &written {
       debug!("written {}:{}/{}", info.id, info.version, name);
+    }
+    output_files += written.len();
+    written.sort();
+
+    regenerated.insert(info.name.clone());
+    outputs.insert(info.name.clone(), DependencyOutput::new(info, written, path));
+    append_manifest_line(&mut manifest, definition).await?;
+  }
+  progress::finish();
+
+  if args.dry_run {
+    let stale = if args.prune {
+      find_stale_outputs(out, &resources, &args.output_namespace_layout)?
+    } else {
+      Vec::new()
+    };
+    for path in &stale {
+      println!("{} would be pruned", path.display());
+    }
+    report_validation_errors(validation_errors)?;
+    // Pending changes fail the run, so CI can require the output to be up to date
+    if !regenerated.is_empty() || !stale.is_empty() {
+      bail!(
+        "dry run: {} resources would be regenerated and {} output directories pruned",
+        regenerated.len(),
+        stale.len()
+      );
+    }
+    info!("dry run: no changes pending");
+    return Ok(());
+  }
+
+  // Written after generation, so resources that failed validation or generation aren't recorded as up to date
+  debug!("writing mtimes file...");
+  write_mtimes(&mtimes_file, resource_actual_mtimes, &content_hashes, args.cache_mode).await?;
 
-      output_files += 1;
+  if let Some(mut manifest) = manifest {
+    manifest.flush().await?;
+  } else {
+    fs::write(out.join("00-resources.json"), serde_json::to_vec_pretty(&resources)?).await?;
+  }
+  let content_files = match args.output_layout {
+    OutputLayout::Octal => HashMap::new(),
+    OutputLayout::Content => {
+      info!("storing output files by content...");
+      store_content(out, &outputs).await?
+    }
+  };
+  // Read back from the chunk index, so chunks of cached proplibs are recorded too
+  let mut chunk_files = HashMap::new();
+  for definition in &resources {
+    let ResourceDefinition::Proplib(resource) = definition else {
+      continue;
+    };
+    let info = resource.get_info().as_ref().unwrap();
+    let Some(output) = outputs.get(&info.name) else {
+      continue;
+    };
+    if !output.files.iter().any(|file| file == "library.index.json") {
+      continue;
+    }
+    let file = output.path.join("library.index.json");
+    let index: ChunkIndex = serde_json::from_slice(&read_file(&file).await?)
+      .with_context(|| format!("failed to parse chunk index {}", file.display()))?;
+    let chunks = index.chunks.into_iter().map(|chunk| chunk.file).collect::<Vec<_>>();
+    chunk_files.insert(info.name.as_str(), chunks);
+  }
+  // Read from the written file, so sizes of cached sound banks are recorded too
+  let mut bank_sizes = HashMap::new();
+  for definition in &resources {
+    let ResourceDefinition::SoundBank(resource) = definition else {
+      continue;
+    };
+    let info = resource.get_info().as_ref().unwrap();
+    let Some(output) = outputs.get(&info.name) else {
+      continue;
+    };
+    if !output.files.iter().any(|file| file == "bank.tara") {
+      continue;
+    }
+    let file = output.path.join("bank.tara");
+    let metadata = fs::metadata(&file)
+      .await
+      .with_context(|| format!("failed to read {}", file.display()))?;
+    bank_sizes.insert(info.name.as_str(), metadata.len());
+  }
+  // Cached resources are listed too, so the manifest is complete after every run
+  let build_manifest = resources
+    .iter()
+    .map(|definition| {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      BuildManifestEntry {
+        name: &info.name,
+        id: info.id,
+        version: info.version,
+        kind: definition.kind(),
+        namespaces: info
+          .namespaces
+          .iter()
+          .map(|(key, value)| (key.as_str(), value.as_str()))
+          .collect(),
+        path: &info.path,
+        output_files: outputs
+          .get(&info.name)
+          .map(|output| output.files.as_slice())
+          .unwrap_or_default(),
+        compression: match definition {
+          ResourceDefinition::Proplib(_)
+          | ResourceDefinition::MultiframeTexture(_)
+          | ResourceDefinition::SoundBank(_)
+            if args.compress != ArchiveCompression::None =>
+          {
+            Some(args.compress)
+          }
+          ResourceDefinition::Texture(resource)
+            if resource.alpha.is_some() && args.compress != ArchiveCompression::None =>
+          {
+            Some(args.compress)
+          }
+          _ => None,
+        },
+        format: match definition {
+          ResourceDefinition::Sound(resource) => Some(resource.get_format()),
+          _ => None,
+        },
+        content: content_files.get(&info.name),
+        chunks: chunk_files.get(info.name.as_str()).map(Vec::as_slice),
+        size: bank_sizes.get(info.name.as_str()).copied(),
+      }
+    })
+    .collect::<Vec<_>>();
+  fs::write(out.join("manifest.json"), serde_json::to_vec_pretty(&build_manifest)?).await?;
+  if args.generated_json {
+    fs::write(out.join("generated.json"), serde_json::to_vec_pretty(&generated)?).await?;
+  }
+  if args.timings {
+    timings.sort_by(|a, b| b.ms.cmp(&a.ms).then_with(|| a.name.cmp(&b.name)));
+    let mut csv = "name,kind,ms,bytes\n".to_owned();
+    for timing in &timings {
+      csv += &format!(
+        "{},{},{},{}\n",
+        csv_field(&timing.name),
+        timing.kind,
+        timing.ms,
+        timing.bytes
+      );
     }
+    fs::write(out.join("timings.csv"), csv).await?;
+  }
+  let sizes = measure_output_sizes(&resources, &outputs).await?;
+  for (kind, size) in &sizes {
+    info!(
+      "{}: {} bytes in {} files of {} resources",
+      kind, size.bytes, size.files, size.resources
+    );
+  }
+  if args.size_report {
+    fs::write(out.join("size-report.json"), serde_json::to_vec_pretty(&sizes)?).await?;
+  }
+  if args.report_unused_props {
+    let unused = find_unused_props(&resources, &proplibs).await?;
+    info!("{} proplib props are not placed on any map", unused.len());
+    let content = unused.iter().map(|prop| format!("{}\n", prop)).collect::<String>();
+    fs::write(out.join("unused-props.txt"), content).await?;
+  }
+  if let (Some(old_manifest), Some(patch_out)) = (&args.patch_against, &args.patch_out) {
+    write_patch(&resources, out, old_manifest, patch_out).await?;
+  }
+  if args.prune {
+    let pruned = prune_outputs(out, &resources, &args.output_namespace_layout)?;
+    info!("pruned {} stale output directories", pruned);
+  }
+  if let Some(file) = &args.export_cache {
+    info!("exporting content cache to {}...", file.display());
+    let cache = ContentCache { files: content_hashes };
+    fs::write(file, serde_json::to_vec_pretty(&cache)?)
+      .await
+      .with_context(|| format!("failed to write content cache {}", file.display()))?;
   }
 
-  fs::write("out/00-resources.json", serde_json::to_vec_pretty(&resources)?).await?;
+  report_validation_errors(validation_errors)?;
 
   let end = Instant::now();
   info!("completed in {:?}", end - start);
@@ -516,61 +756,2001 @@ async fn main() -> Result<()> {
     input_files
   );
 
+  let warnings = diagnostics::count(Severity::Warning);
+  if warnings > 0 {
+    // Summary of the collected diagnostics, counting itself would be circular
+    warn!("emitted {} warnings", warnings);
+    if args.deny_warnings {
+      bail!("{} warnings emitted with --deny-warnings", warnings);
+    }
+  }
+
   Ok(())
 }
 
-fn file_exists_case_insensitive<P: AsRef<Path>>(filename: P) -> Option<PathBuf> {
-  let filename_str = filename.as_ref().file_name().unwrap().to_str().unwrap().to_lowercase();
-  let parent_dir = filename.as_ref().parent().unwrap_or_else(|| Path::new("."));
+/// Returns the filter of `RUST_LOG` if it is set, the level selected by `--quiet` and `--verbose` otherwise.
+fn log_filter() -> EnvFilter {
+  if let Ok(filter) = EnvFilter::try_from_default_env() {
+    return filter;
+  }
+  let args = cli::args();
+  let level = match args.verbose {
+    _ if args.quiet => LevelFilter::ERROR,
+    0 => LevelFilter::WARN,
+    1 => LevelFilter::INFO,
+    2 => LevelFilter::DEBUG,
+    _ => LevelFilter::TRACE,
+  };
+  EnvFilter::new(level.to_string())
+}
 
-  for entry in WalkDir::new(parent_dir).max_depth(1).into_iter().flatten() {
-    if entry.file_type().is_file() {
-      let entry_filename = entry.file_name().to_str().unwrap().to_lowercase();
-      if entry_filename == filename_str {
-        return Some(entry.into_path());
-      }
+/// Fails if `out` is `root` or inside it, as the next scan would pick up generated files as inputs.
+fn validate_output_dir(out: &Path, root: &Path) -> Result<()> {
+  let resolved_out = resolve_path(out)?;
+  let resolved_root = resolve_path(root)?;
+  if resolved_out.starts_with(&resolved_root) {
+    bail!(
+      "output directory {} ({}) is inside resources directory {} ({})",
+      out.display(),
+      resolved_out.display(),
+      root.display(),
+      resolved_root.display()
+    );
+  }
+
+  Ok(())
+}
+
+/// Fails with every validation error collected while generating, logging each of them.
+fn report_validation_errors(validation_errors: ValidationErrors) -> Result<()> {
+  if !validation_errors.is_empty() {
+    let errors = validation_errors.into_vec();
+    for error in &errors {
+      error!("{}", error);
     }
+    let missing_files = errors
+      .iter()
+      .filter_map(|error| error.file.as_ref())
+      .collect::<HashSet<_>>();
+    bail!(
+      "validation failed with {} errors ({} distinct files missing)",
+      errors.len(),
+      missing_files.len()
+    );
   }
 
-  None
+  Ok(())
 }
 
-#[allow(irrefutable_let_patterns)]
-fn get_texture_map_name(main: &Main) -> Option<String> {
-  if let Main::Editor(editors) = main {
-    for editor in editors {
-      if let Editor::Material(materials) = editor {
-        for material in materials {
-          if let Material::TextureMap(texture_maps) = material {
-            for texture_map in texture_maps {
-              if let MaterialTextureMap::Name(name) = texture_map {
-                return Some(name.clone());
-              }
-            }
-          }
-        }
+/// Removes output directories of resources that were not discovered in this run, including
+/// old versions of existing resources. Only directories at `<id>/<id>/<id>/<id>/<version>` in octal,
+/// optionally prefixed with the namespace layout, are considered, so other files in `out` are kept.
+/// Returns the number of removed directories.
+fn prune_outputs(out: &Path, resources: &[ResourceDefinition], layout: &[String]) -> Result<usize> {
+  let stale = find_stale_outputs(out, resources, layout)?;
+  for path in &stale {
+    info!("pruning stale output directory {}", path.display());
+    std::fs::remove_dir_all(path).with_context(|| format!("failed to remove {}", path.display()))?;
+
+    // Drop parents left empty, up to the output directory
+    let mut parent = path.parent();
+    while let Some(directory) = parent {
+      if directory == out || std::fs::read_dir(directory)?.next().is_some() {
+        break;
       }
+      std::fs::remove_dir(directory).with_context(|| format!("failed to remove {}", directory.display()))?;
+      parent = directory.parent();
+    }
+  }
+
+  Ok(stale.len())
+}
+
+/// Returns the output directories that match the encoded id/version layout but belong to no resource.
+fn find_stale_outputs(out: &Path, resources: &[ResourceDefinition], layout: &[String]) -> Result<Vec<PathBuf>> {
+  const ENCODED_COMPONENTS: usize = 5;
+
+  let expected = resources
+    .iter()
+    .map(|definition| PathBuf::from(&definition.resource().get_info().as_ref().unwrap().path))
+    .collect::<HashSet<_>>();
+
+  let mut stale = Vec::new();
+  for entry in WalkDir::new(out)
+    .min_depth(ENCODED_COMPONENTS)
+    .max_depth(ENCODED_COMPONENTS + layout.len())
+  {
+    let entry = entry?;
+    if !entry.file_type().is_dir()
+      || (entry.depth() != ENCODED_COMPONENTS && entry.depth() != ENCODED_COMPONENTS + layout.len())
+    {
+      continue;
+    }
+
+    let relative = entry.path().strip_prefix(out)?;
+    let mut encoded = relative
+      .components()
+      .rev()
+      .take(ENCODED_COMPONENTS)
+      .map(|component| component.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>();
+    encoded.reverse();
+    let encoded = ResourceInfo::decode(&encoded.join("/")).is_some();
+    // Directories of a resource only contain files, this rules out namespace values that look like ids
+    let leaf = std::fs::read_dir(entry.path())?.all(|child| child.is_ok_and(|child| !child.path().is_dir()));
+    if encoded && leaf && !expected.contains(relative) {
+      stale.push(entry.into_path());
     }
   }
-  None
+
+  Ok(stale)
 }
 
-async fn get_namespaces(path: &Path) -> HashMap<String, String> {
-  let mut namespaces = HashMap::new();
+/// Returns the absolute path with symlinks resolved, for paths that may not exist yet.
+fn resolve_path(path: &Path) -> Result<PathBuf> {
+  let path = std::env::current_dir()?.join(path);
+  let mut existing = path.as_path();
+  let mut missing = Vec::new();
+  while !existing.try_exists()? {
+    let (Some(name), Some(parent)) = (existing.components().next_back(), existing.parent()) else {
+      break;
+    };
+    missing.push(name.as_os_str().to_owned());
+    existing = parent;
+  }
 
-  for component in path.components() {
-    if let Some(comp_str) = component.as_os_str().to_str() {
-      // Check if the component matches the pattern @key=value
-      if let Some(namespace) = comp_str.strip_prefix('@') {
-        let parts: Vec<&str> = namespace.split('=').collect();
-        if parts.len() == 2 {
-          let key = parts[0].to_string();
-          let value = parts[1].to_string();
-          namespaces.insert(key, value);
-        }
+  let mut resolved = existing
+    .canonicalize()
+    .with_context(|| format!("failed to resolve {}", path.display()))?;
+  resolved.extend(missing.iter().rev());
+  Ok(resolved)
+}
+
+/// A path found by the tree walk that holds a resource definition.
+enum Candidate {
+  /// Directory containing a `resource.yaml`.
+  Full(PathBuf),
+  /// File named `<name>@<Kind>.<extension>`, or directory named `<name>@<Kind>` for [SHORT_DIRECTORY_KINDS].
+  Short(PathBuf),
+}
+
+impl Candidate {
+  /// Name of the resource, known without reading its definition.
+  fn name(&self, root: &Path) -> Result<String> {
+    match self {
+      Candidate::Full(path) => path_name(root, path),
+      Candidate::Short(path) => {
+        let (name, _, _) = parse_short_name(path).unwrap();
+        Ok(path_name(root, path.parent().unwrap())? + "." + name)
       }
     }
   }
+}
 
-  namespaces
+/// A resource definition read from disk, before its version is computed.
+struct DiscoveredResource {
+  definition: ResourceDefinition,
+  name: String,
+  id: u32,
+  properties: ResourceProperties,
+  /// The `resource.yaml` or the short definition file itself, none for short definition directories.
+  definition_file: Option<PathBuf>,
+}
+
+/// Result of scanning a single resource, merged into the shared state once all scans complete.
+struct ScannedResource {
+  definition: ResourceDefinition,
+  changed: bool,
+  /// Input files (relative to the resources root) that are new or changed since the last run.
+  triggered_by: Vec<String>,
+  /// Human-readable reasons the resource is considered changed, printed by `--explain`.
+  reasons: Vec<String>,
+  mtimes: HashMap<String, u128>,
+  /// Content hashes of input files, only computed with `--cache-mode hash` or when a content cache is imported or exported.
+  hashes: HashMap<String, u32>,
+  input_files: usize,
+  /// Input files reported by the resource kind that don't exist on disk.
+  missing_files: Vec<PathBuf>,
+  /// Resource directory or short definition file the resource was read from.
+  source: PathBuf,
+}
+
+/// Change detection state written by `--export-cache` and read by `--import-cache`.
+/// Unlike `out/mtimes`, content hashes stay valid across checkouts.
+#[derive(Debug, Serialize, Deserialize)]
+struct ContentCache {
+  /// CRC32 of each input file, keyed by path relative to the resources root.
+  files: BTreeMap<String, u32>,
+}
+
+/// Resource identity read from a previous `00-resources.json` or `00-resources.ndjson`.
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+  info: ManifestInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestInfo {
+  id: i64,
+  version: i64,
+}
+
+/// Entry of `manifest.json`, a compact index of all resources and their output files.
+#[derive(Debug, Serialize)]
+struct BuildManifestEntry<'a> {
+  name: &'a str,
+  id: i64,
+  version: i64,
+  kind: Option<ResourceKind>,
+  namespaces: BTreeMap<&'a str, &'a str>,
+  /// Output directory relative to the output root
+  path: &'a str,
+  /// Output files relative to `path`
+  output_files: &'a [String],
+  /// Compression of the `.tara` archives of the resource, if they are compressed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  compression: Option<ArchiveCompression>,
+  /// Container of sounds
+  #[serde(skip_serializing_if = "Option::is_none")]
+  format: Option<SoundFormat>,
+  /// SHA-256 of each output file, stored as `content/<first 2 digits>/<digest>` with `--output-layout content`
+  #[serde(skip_serializing_if = "Option::is_none")]
+  content: Option<&'a BTreeMap<String, String>>,
+  /// `.tara` chunks of a proplib split with `chunk_size`, in the order the client concatenates them
+  #[serde(skip_serializing_if = "Option::is_none")]
+  chunks: Option<&'a [String]>,
+  /// Size of the written `bank.tara` of a sound bank, after compression
+  #[serde(skip_serializing_if = "Option::is_none")]
+  size: Option<u64>,
+}
+
+/// Entry of `size-report.json`.
+#[derive(Debug, Default, Serialize)]
+struct KindSize {
+  resources: usize,
+  files: usize,
+  bytes: u64,
+}
+
+/// Sums the sizes of the outputs of every resource in `out` by kind, including cached resources.
+async fn measure_output_sizes(
+  resources: &[ResourceDefinition],
+  outputs: &HashMap<String, DependencyOutput>,
+) -> Result<BTreeMap<&'static str, KindSize>> {
+  let mut sizes = BTreeMap::<_, KindSize>::new();
+  for definition in resources {
+    let info = definition.resource().get_info().as_ref().unwrap();
+    let Some(output) = outputs.get(&info.name) else {
+      continue;
+    };
+    let size = sizes.entry(definition.kind_name()).or_default();
+    size.resources += 1;
+    for file in &output.files {
+      let file = output.path.join(file);
+      size.files += 1;
+      size.bytes += fs::metadata(&file)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?
+        .len();
+    }
+  }
+
+  Ok(sizes)
+}
+
+/// Row of `timings.csv`.
+struct ResourceTiming {
+  name: String,
+  kind: &'static str,
+  ms: u128,
+  /// Total size of the written outputs
+  bytes: u64,
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_owned()
+  }
+}
+
+/// Entry of `generated.json`, listing what caused a resource to be regenerated.
+#[derive(Debug, Serialize)]
+struct GeneratedResource {
+  resource: String,
+  triggered_by: Vec<String>,
+}
+
+/// Computes the id of a resource from its path. Ids have always been derived from the path under
+/// the default `resources` directory, which is kept so building a tree from another `--input` keeps its ids.
+fn path_id(root: &Path, path: &Path) -> Result<u32> {
+  let path = Path::new("resources").join(path.strip_prefix(root)?);
+  Ok(CRC.checksum(path.to_string_lossy().as_bytes()))
+}
+
+/// Joins the directories of a resource path into its dotted name, skipping namespace directories.
+fn path_name(root: &Path, path: &Path) -> Result<String> {
+  Ok(
+    path
+      .strip_prefix(root)?
+      .components()
+      .map(|component| component.as_os_str().to_str().unwrap())
+      .filter(|component| !component.starts_with("@"))
+      .collect::<Vec<_>>()
+      .join("."),
+  )
+}
+
+/// Reads the `--exclude` patterns and the ones listed in [RESOURCE_IGNORE_FILE].
+async fn read_exclude_patterns(root: &Path) -> Result<Vec<Pattern>> {
+  let mut patterns = cli::args()
+    .exclude
+    .iter()
+    .map(|pattern| Pattern::new(pattern).with_context(|| format!("invalid --exclude pattern {pattern}")))
+    .collect::<Result<Vec<_>>>()?;
+
+  let file = root.join(RESOURCE_IGNORE_FILE);
+  if file.try_exists()? {
+    let content = files::read_to_string(&file)
+      .await
+      .with_context(|| format!("failed to read {}", file.display()))?;
+    for (index, line) in content.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      patterns.push(
+        Pattern::new(line).with_context(|| format!("{}:{}: invalid pattern {}", file.display(), index + 1, line))?,
+      );
+    }
+  }
+
+  Ok(patterns)
+}
+
+/// Splits `candidates` into the ones not matching `exclude` and the sorted names of the excluded ones.
+fn exclude_candidates(
+  candidates: Vec<Candidate>,
+  root: &Path,
+  exclude: &[Pattern],
+) -> Result<(Vec<Candidate>, Vec<String>)> {
+  let mut excluded = Vec::new();
+  let mut included = Vec::new();
+  for candidate in candidates {
+    let name = candidate.name(root)?;
+    if exclude.iter().any(|pattern| pattern.matches(&name)) {
+      excluded.push(name);
+    } else {
+      included.push(candidate);
+    }
+  }
+  excluded.sort();
+  Ok((included, excluded))
+}
+
+/// Splits a short definition file name `<name>@<Kind>.<extension>` into its parts.
+fn parse_short_name(path: &Path) -> Option<(&str, &str, &str)> {
+  let file_name = path.file_name()?.to_str()?;
+  let (file_name, extension) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+  let (name, kind) = file_name.rsplit_once('@')?;
+  Some((name, kind, extension))
+}
+
+async fn read_full_definition(root: &Path, path: &Path) -> Result<DiscoveredResource> {
+  let definition_path = path.join(RESOURCE_DEFINITION_FILE);
+  let definition = files::read_to_string(&definition_path)
+    .await
+    .with_context(|| format!("failed to read definition {}", definition_path.display()))?;
+  let properties: ResourceProperties = parse_yaml(&definition, &definition_path)?;
+  let mut definition = parse_definition(&definition, &definition_path)?;
+  definition.resource_mut().init_root(path.to_path_buf());
+
+  let name = path_name(root, path)?;
+  let id = match properties.id {
+    Some(id) => id,
+    None => path_id(root, path)?,
+  };
+
+  Ok(DiscoveredResource {
+    definition,
+    name,
+    id,
+    properties,
+    definition_file: Some(definition_path),
+  })
+}
+
+/// Kinds that can be declared by a short definition.
+const SHORT_KINDS: [&str; 11] = [
+  "Sound",
+  "Map",
+  "Proplib",
+  "Texture",
+  "Image",
+  "MultiframeTexture",
+  "LocalizedImage",
+  "Object3D",
+  "SwfLibrary",
+  "GameObject",
+  "RawData",
+];
+
+/// Kinds whose short definition is a directory that becomes the resource root.
+const SHORT_DIRECTORY_KINDS: [&str; 2] = ["Proplib", "LocalizedImage"];
+
+/// Extensions of the images an `@Object3D` short definition picks up from its sibling `images` directory.
+const SHORT_OBJECT3D_IMAGE_EXTENSIONS: [&str; 2] = ["jpg", "png"];
+
+async fn read_short_definition(root: &Path, path: &Path) -> Result<DiscoveredResource> {
+  let (name, kind, extension) = parse_short_name(path).unwrap();
+  debug!(?name, ?kind, ?extension, "discovered short resource");
+
+  let mut definition = match kind {
+    "Sound" => ResourceDefinition::Sound(SoundResource {
+      root: Default::default(),
+      info: None,
+      sound: Some(path.to_path_buf()),
+      format: SoundFormat::from_extension(extension),
+    }),
+    "Map" => ResourceDefinition::Map(MapResource {
+      root: Default::default(),
+      info: None,
+      map: Some(path.to_path_buf()),
+      maps: Default::default(),
+      parsed: None,
+      variants: Default::default(),
+      proplibs: Default::default(),
+      proplib_versions: Default::default(),
+      namespace: None,
+    }),
+    "Proplib" => {
+      if !path.is_dir() {
+        bail!(
+          "{}: Proplib short definitions must be a directory named <name>@Proplib, or add a {} to the proplib directory",
+          path.display(),
+          RESOURCE_DEFINITION_FILE
+        );
+      }
+
+      #[allow(deprecated)]
+      let resource = ProplibResource {
+        root: Default::default(),
+        info: None,
+        name: None,
+        namespace: None,
+        chunk_size: None,
+        include_extensions: Vec::new(),
+        exclude_extensions: Vec::new(),
+        library: None,
+        images: None,
+      };
+      ResourceDefinition::Proplib(resource)
+    }
+    "Texture" => ResourceDefinition::Texture(TextureResource {
+      root: Default::default(),
+      info: None,
+      diffuse: Some(path.to_path_buf()),
+      mask: None,
+      alpha: None,
+      normalize_jpeg: None,
+      max_size: None,
+      format: None,
+    }),
+    "Image" => ResourceDefinition::Image(ImageResource {
+      root: Default::default(),
+      info: None,
+      image: Some(path.to_path_buf()),
+      normalize_jpeg: None,
+      max_size: None,
+    }),
+    "MultiframeTexture" => {
+      let properties_file = path.with_file_name(format!("{}.properties", name));
+      let (properties, properties_file) = if properties_file.try_exists()? {
+        let content = files::read_to_string(&properties_file)
+          .await
+          .with_context(|| format!("failed to read {}", properties_file.display()))?;
+        (
+          parse_properties_file(&content, &properties_file)?,
+          Some(properties_file),
+        )
+      } else {
+        (MultiframeTextureLayout::default(), None)
+      };
+      let alpha = path.with_file_name(format!("{}.alpha.{}", name, extension));
+
+      ResourceDefinition::MultiframeTexture(MultiframeTextureResource {
+        root: Default::default(),
+        info: None,
+        diffuse: Some(path.to_path_buf()),
+        alpha: alpha.try_exists()?.then_some(alpha),
+        properties,
+        properties_file,
+        resolved: None,
+      })
+    }
+    "LocalizedImage" => ResourceDefinition::LocalizedImage(LocalizedImageResource {
+      root: Default::default(),
+      info: None,
+    }),
+    "Object3D" => {
+      // Every image in the sibling `images` directory, named by file stem
+      let images = if path.with_file_name("images").is_dir() {
+        SHORT_OBJECT3D_IMAGE_EXTENSIONS
+          .iter()
+          .map(|extension| {
+            (format!("*.{}", extension), Object3DImage::Glob {
+              glob: format!("images/*.{}", extension),
+            })
+          })
+          .collect()
+      } else {
+        HashMap::new()
+      };
+
+      ResourceDefinition::Object3D(Object3DResource {
+        root: Default::default(),
+        info: None,
+        object: Some(path.to_path_buf()),
+        images,
+      })
+    }
+    "SwfLibrary" => ResourceDefinition::SwfLibrary(SwfLibraryResource {
+      root: Default::default(),
+      info: None,
+      file: Some(path.to_path_buf()),
+    }),
+    "GameObject" => ResourceDefinition::GameObject(GameObjectResource {
+      root: Default::default(),
+      info: None,
+      file: Some(path.to_path_buf()),
+    }),
+    "RawData" => ResourceDefinition::RawData(RawDataResource {
+      root: Default::default(),
+      info: None,
+      files: vec![RawDataFile::Named {
+        file: path.to_path_buf(),
+        output: if extension.is_empty() {
+          name.to_owned()
+        } else {
+          format!("{}.{}", name, extension)
+        },
+      }],
+    }),
+    _ => unreachable!("unknown short definition kinds are skipped by the scan"),
+  };
+  // A short definition directory is the root of its resource
+  let resource_root = if path.is_dir() { path } else { path.parent().unwrap() };
+  definition.resource_mut().init_root(resource_root.to_path_buf());
+
+  let name = path_name(root, path.parent().unwrap())? + "." + name;
+  let id = path_id(root, path)?;
+
+  Ok(DiscoveredResource {
+    definition,
+    name,
+    id,
+    properties: Default::default(),
+    definition_file: path.is_file().then(|| path.to_path_buf()),
+  })
+}
+
+/// Maximum number of input files of a resource held in memory while computing its version.
+const VERSION_READ_AHEAD: usize = 8;
+
+/// Files larger than this are streamed into the version digest instead of being read ahead.
+const VERSION_STREAM_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+enum VersionInput<'a> {
+  Data(Vec<u8>),
+  Stream(&'a Path),
+}
+
+/// Reads a resource definition, detects whether its inputs changed since the last run
+/// and computes its version. Touches no shared state, so scans can run concurrently.
+async fn scan_resource(
+  candidate: &Candidate,
+  root: &Path,
+  cached_mtimes: &HashMap<String, u128>,
+  cached_hashes: Option<&BTreeMap<String, u32>>,
+) -> Result<ScannedResource> {
+  let (path, discovered) = match candidate {
+    Candidate::Full(path) => (path, read_full_definition(root, path).await?),
+    Candidate::Short(path) => (path, read_short_definition(root, path).await?),
+  };
+  let DiscoveredResource {
+    mut definition,
+    name,
+    id,
+    properties,
+    definition_file,
+  } = discovered;
+
+  let namespaces = get_namespaces(path.strip_prefix(root)?).await;
+  debug!(?name, ?id, ?namespaces, "resource");
+  check_required_namespaces(&name, &namespaces, &cli::args().require_namespace)?;
+
+  let mut raw_input_files = definition.resource().input_files().await?;
+  raw_input_files.extend(definition_file);
+  let preprocessed_input_files = preprocess_input_files(&raw_input_files)?;
+  let missing_files = raw_input_files
+    .iter()
+    .filter(|file| !preprocessed_input_files.contains(&file.as_path()))
+    .cloned()
+    .collect();
+
+  let hashing = cached_hashes.is_some() || cli::args().export_cache.is_some();
+  let mut mtimes = HashMap::new();
+  let mut hashes = HashMap::new();
+  let mut triggered_by = Vec::new();
+  let mut reasons = Vec::new();
+  for file in &preprocessed_input_files {
+    if file.is_dir() {
+      continue;
+    }
+
+    let cache_path = file.strip_prefix(root).unwrap().to_str().unwrap();
+
+    let actual_mtime = fs::metadata(file)
+      .await
+      .unwrap()
+      .modified()
+      .map(|time| time.duration_since(UNIX_EPOCH).unwrap().as_millis())
+      .expect("unsupported platform");
+    mtimes.insert(cache_path.to_owned(), actual_mtime);
+
+    let actual_hash = if hashing {
+      let hash = CRC.checksum(&read_file(file).await?);
+      hashes.insert(cache_path.to_owned(), hash);
+      Some(hash)
+    } else {
+      None
+    };
+
+    let unchanged = match cached_hashes {
+      Some(cached_hashes) => cached_hashes.get(cache_path).map(|hash| Some(*hash) == actual_hash),
+      None => cached_mtimes.get(cache_path).map(|mtime| *mtime == actual_mtime),
+    };
+    match unchanged {
+      Some(true) => {
+        debug!("{} has not changed", file.display());
+        continue;
+      }
+      Some(false) => {
+        debug!("{} has changed", file.display());
+        let compared = if cached_hashes.is_some() { "content" } else { "mtime" };
+        reasons.push(format!("{}: {} changed", cache_path, compared));
+      }
+      None if cached_hashes.is_some() && cached_mtimes.contains_key(cache_path) => {
+        debug!("no cached hash for {}", file.display());
+        reasons.push(format!("{}: no cached content hash", cache_path));
+      }
+      None => {
+        debug!("new file {}", file.display());
+        reasons.push(format!("{}: new file", cache_path));
+      }
+    }
+    triggered_by.push(cache_path.to_owned());
+  }
+
+  let mut changed = !triggered_by.is_empty();
+
+  if matches!(definition, ResourceDefinition::Localization(_)) && cli::args().force_localization {
+    debug!("regenerating localization {} because of --force-localization", name);
+    reasons.push("--force-localization regenerates localization resources".to_owned());
+    changed = true;
+  } else if !changed {
+    debug!("skipping {} as no files have been changed", name);
+  }
+
+  let version_ignore = properties
+    .version_ignore
+    .iter()
+    .map(|pattern| Pattern::new(pattern).with_context(|| format!("invalid version_ignore pattern {pattern}")))
+    .collect::<Result<Vec<_>>>()?;
+  let match_options = MatchOptions {
+    require_literal_separator: true,
+    ..MatchOptions::new()
+  };
+  let resource_root = definition.resource().get_root();
+
+  let digest_files = match cli::args().version_order {
+    VersionOrder::Path => preprocessed_input_files.clone(),
+    VersionOrder::Input => raw_input_files
+      .iter()
+      .map(PathBuf::as_path)
+      .filter(|file| preprocessed_input_files.contains(file))
+      .collect(),
+  };
+
+  let mut input_files = 0;
+  let mut versioned_files = Vec::new();
+  for file in &digest_files {
+    if file.is_dir() {
+      continue;
+    }
+    input_files += 1;
+
+    let relative = file.strip_prefix(&resource_root).unwrap_or(*file);
+    if version_ignore
+      .iter()
+      .any(|pattern| pattern.matches_path_with(relative, match_options))
+    {
+      trace!("ignoring {} when calculating version for {}", file.display(), name);
+      continue;
+    }
+
+    trace!("using {} to calculate version for {}", file.display(), name);
+    versioned_files.push(*file);
+  }
+
+  let version = compute_version(definition.resource(), versioned_files).await?;
+
+  let mut info = ResourceInfo {
+    name,
+    id: id as i64,
+    version: version as i64,
+    namespaces,
+    path: String::new(),
+    properties,
+  };
+  info.path = info.output_path(&cli::args().output_namespace_layout)?;
+  definition.resource_mut().init(info).await?;
+  debug!("read resource definition {}: {:?}", path.display(), definition);
+
+  Ok(ScannedResource {
+    definition,
+    changed,
+    triggered_by,
+    reasons,
+    mtimes,
+    hashes,
+    input_files,
+    missing_files,
+    source: path.clone(),
+  })
+}
+
+/// Digests the version of a resource from its input files, in the order they are given.
+async fn compute_version(resource: &dyn Resource, files: Vec<&Path>) -> Result<u32> {
+  // Files are read concurrently, `buffered` keeps them in digest order.
+  // Large files are streamed into the digest when their turn comes instead of being held in memory
+  let mut contents = stream::iter(files)
+    .map(|file| async move {
+      if let Some(data) = resource.version_data(file).await? {
+        return Ok(VersionInput::Data(data));
+      }
+      let length = fs::metadata(file)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?
+        .len();
+      if length > VERSION_STREAM_THRESHOLD {
+        return Ok(VersionInput::Stream(file));
+      }
+      let data = files::read(file)
+        .await
+        .with_context(|| format!("failed to read {}", file.display()))?;
+      anyhow::Ok(VersionInput::Data(data))
+    })
+    .buffered(VERSION_READ_AHEAD);
+  let mut digest = VersionDigest::new();
+  while let Some(input) = contents.next().await {
+    match input? {
+      VersionInput::Data(data) => digest.update_file(&data),
+      VersionInput::Stream(file) => {
+        let length = files::read_chunks(file, |chunk| digest.update(chunk))
+          .await
+          .with_context(|| format!("failed to read {}", file.display()))?;
+        digest.end_file(length);
+      }
+    }
+  }
+  Ok(digest.finalize())
+}
+
+/// Checks that a resource produced exactly the files listed in its `expected_outputs`, if any.
+fn verify_outputs(info: &ResourceInfo, output: &[String]) -> Result<()> {
+  let Some(expected) = &info.properties.expected_outputs else {
+    return Ok(());
+  };
+
+  let missing = expected
+    .iter()
+    .filter(|name| !output.contains(*name))
+    .cloned()
+    .collect::<BTreeSet<_>>();
+  let unexpected = output
+    .iter()
+    .filter(|name| !expected.contains(*name))
+    .cloned()
+    .collect::<BTreeSet<_>>();
+  if !missing.is_empty() || !unexpected.is_empty() {
+    bail!(
+      "resource {} outputs don't match expected_outputs: missing {:?}, unexpected {:?}",
+      info.name,
+      missing,
+      unexpected
+    );
+  }
+
+  Ok(())
+}
+
+/// Reads resource names from a `--build-list` file, one per line, and adds the resources they
+/// depend on. Empty lines and lines starting with `#` are ignored.
+async fn read_build_list(file: &Path, resources: &[ResourceDefinition]) -> Result<HashSet<String>> {
+  let content = fs::read_to_string(file)
+    .await
+    .with_context(|| format!("failed to read build list {}", file.display()))?;
+  let infos = resources
+    .iter()
+    .map(|definition| {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      (info.name.as_str(), info)
+    })
+    .collect::<HashMap<_, _>>();
+
+  let mut pending = Vec::new();
+  let mut unknown = Vec::new();
+  for name in content.lines().map(str::trim) {
+    if name.is_empty() || name.starts_with('#') {
+      continue;
+    }
+    if infos.contains_key(name) {
+      pending.push(name);
+    } else {
+      unknown.push(name);
+    }
+  }
+  if !unknown.is_empty() {
+    bail!(
+      "build list {} contains unknown resources: {}",
+      file.display(),
+      unknown.join(", ")
+    );
+  }
+
+  Ok(with_dependencies(pending, &infos))
+}
+
+/// Selects the resources whose name matches one of `patterns` for `--only`.
+fn select_matching_resources(patterns: &[String], resources: &[ResourceDefinition]) -> Result<HashSet<String>> {
+  let infos = resources
+    .iter()
+    .map(|definition| {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      (info.name.as_str(), info)
+    })
+    .collect::<HashMap<_, _>>();
+
+  let mut pending = Vec::new();
+  for pattern in patterns {
+    let glob = Pattern::new(pattern).with_context(|| format!("invalid --only pattern {pattern}"))?;
+    let matched = infos.keys().filter(|name| glob.matches(name)).collect::<Vec<_>>();
+    if matched.is_empty() {
+      diagnostics::warning(format!("--only pattern {} matches no resources", pattern));
+    }
+    pending.extend(matched.into_iter().copied());
+  }
+
+  Ok(with_dependencies(pending, &infos))
+}
+
+/// Selects the resources that have every namespace of `namespaces` for `--namespace`.
+fn select_namespace_resources(namespaces: &[(String, String)], resources: &[ResourceDefinition]) -> HashSet<String> {
+  let infos = resources
+    .iter()
+    .map(|definition| {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      (info.name.as_str(), info)
+    })
+    .collect::<HashMap<_, _>>();
+
+  let pending = infos
+    .iter()
+    .filter(|(_, info)| {
+      namespaces
+        .iter()
+        .all(|(key, value)| info.namespaces.get(key) == Some(value))
+    })
+    .map(|(name, _)| *name)
+    .collect::<Vec<_>>();
+  if pending.is_empty() {
+    diagnostics::warning("--namespace matches no resources");
+  }
+
+  with_dependencies(pending, &infos)
+}
+
+/// Adds the resources listed in `depends_on` of the `pending` ones, transitively.
+fn with_dependencies(mut pending: Vec<&str>, infos: &HashMap<&str, &ResourceInfo>) -> HashSet<String> {
+  let mut selected = HashSet::new();
+  while let Some(name) = pending.pop() {
+    if selected.insert(name.to_owned()) {
+      // Unknown dependencies are reported by generation_order
+      if let Some(info) = infos.get(name) {
+        pending.extend(info.properties.depends_on.iter().map(String::as_str));
+      }
+    }
+  }
+  selected
+}
+
+/// Orders resources so that each one comes after the resources listed in its `depends_on`,
+/// keeping the scan order (sorted by name) between independent resources.
+fn generation_order(resources: &[ResourceDefinition]) -> Result<Vec<usize>> {
+  let indices = resources
+    .iter()
+    .enumerate()
+    .map(|(index, definition)| (definition.resource().get_info().as_ref().unwrap().name.as_str(), index))
+    .collect::<HashMap<_, _>>();
+
+  let mut dependents = vec![Vec::new(); resources.len()];
+  let mut remaining = vec![0; resources.len()];
+  for (index, definition) in resources.iter().enumerate() {
+    let info = definition.resource().get_info().as_ref().unwrap();
+    for dependency in &info.properties.depends_on {
+      let Some(&dependency) = indices.get(dependency.as_str()) else {
+        bail!("resource {} depends on unknown resource {}", info.name, dependency);
+      };
+      dependents[dependency].push(index);
+      remaining[index] += 1;
+    }
+  }
+
+  let mut ready = (0..resources.len())
+    .filter(|&index| remaining[index] == 0)
+    .collect::<BTreeSet<_>>();
+  let mut order = Vec::with_capacity(resources.len());
+  while let Some(index) = ready.pop_first() {
+    order.push(index);
+    for &dependent in &dependents[index] {
+      remaining[dependent] -= 1;
+      if remaining[dependent] == 0 {
+        ready.insert(dependent);
+      }
+    }
+  }
+
+  if order.len() < resources.len() {
+    let cycle = (0..resources.len())
+      .filter(|&index| remaining[index] > 0)
+      .map(|index| resources[index].resource().get_info().as_ref().unwrap().name.as_str())
+      .collect::<Vec<_>>();
+    bail!("dependency cycle between resources {}", cycle.join(", "));
+  }
+
+  Ok(order)
+}
+
+/// Lists files written for a resource by a previous run.
+fn list_output_files(path: &Path) -> Vec<String> {
+  let mut files = std::fs::read_dir(path)
+    .into_iter()
+    .flatten()
+    .flatten()
+    .filter_map(|entry| entry.file_name().to_str().map(str::to_owned))
+    .collect::<Vec<_>>();
+  files.sort();
+
+  files
+}
+
+/// Stores every output file under `out/content` by its SHA-256, returning the digests of the files of each resource.
+/// Identical files of different resources and versions are stored once, and stored files are never rewritten.
+async fn store_content(
+  out: &Path,
+  outputs: &HashMap<String, DependencyOutput>,
+) -> Result<HashMap<String, BTreeMap<String, String>>> {
+  let mut stored = 0;
+  let mut content_files = HashMap::new();
+  for (name, output) in outputs {
+    let mut digests = BTreeMap::new();
+    for file in &output.files {
+      let source = output.path.join(file);
+      let data = files::read(&source)
+        .await
+        .with_context(|| format!("failed to read {}", source.display()))?;
+      let digest = format!("{:x}", Sha256::digest(&data));
+      let target = out.join("content").join(&digest[..2]).join(&digest);
+      if !target.try_exists()? {
+        // Renamed into place, so an interrupted run doesn't leave a truncated file under a valid digest
+        let temporary_file = target.with_extension("tmp");
+        fs::create_dir_all(target.parent().unwrap()).await?;
+        files::write(&temporary_file, data)
+          .await
+          .with_context(|| format!("failed to write {}", temporary_file.display()))?;
+        fs::rename(&temporary_file, &target).await?;
+        stored += 1;
+      }
+      digests.insert(file.clone(), digest);
+    }
+    content_files.insert(name.clone(), digests);
+  }
+  info!("stored {} new content files", stored);
+
+  Ok(content_files)
+}
+
+/// Copies the outputs of resources that are new or have another version than in `old_manifest` to `patch_out`,
+/// together with a `00-resources.json` listing only those resources.
+async fn write_patch(
+  resources: &[ResourceDefinition],
+  out: &Path,
+  old_manifest: &Path,
+  patch_out: &Path,
+) -> Result<()> {
+  let content = fs::read_to_string(old_manifest)
+    .await
+    .with_context(|| format!("failed to read manifest {}", old_manifest.display()))?;
+  let entries = match serde_json::from_str::<Vec<ManifestEntry>>(&content) {
+    Ok(entries) => entries,
+    // Streamed manifests have one resource per line
+    Err(_) => content
+      .lines()
+      .filter(|line| !line.trim().is_empty())
+      .map(serde_json::from_str::<ManifestEntry>)
+      .collect::<serde_json::Result<Vec<_>>>()
+      .with_context(|| format!("failed to read manifest {}", old_manifest.display()))?,
+  };
+  let old = entries
+    .iter()
+    .map(|entry| (entry.info.id, entry.info.version))
+    .collect::<HashSet<_>>();
+
+  let mut patched = Vec::new();
+  for definition in resources {
+    let info = definition.resource().get_info().as_ref().unwrap();
+    if old.contains(&(info.id, info.version)) {
+      continue;
+    }
+
+    let source = out.join(&info.path);
+    let target = patch_out.join(&info.path);
+    fs::create_dir_all(&target).await?;
+    // Outputs can be large SWF libraries and sounds, so they are not read into memory
+    for name in list_output_files(&source) {
+      files::copy(source.join(&name), target.join(&name))
+        .await
+        .with_context(|| format!("failed to copy {}", source.join(&name).display()))?;
+    }
+    patched.push(definition);
+  }
+
+  fs::create_dir_all(patch_out).await?;
+  fs::write(
+    patch_out.join("00-resources.json"),
+    serde_json::to_vec_pretty(&patched)?,
+  )
+  .await?;
+  info!(
+    "wrote patch with {} of {} resources to {}",
+    patched.len(),
+    resources.len(),
+    patch_out.display()
+  );
+
+  Ok(())
+}
+
+async fn append_manifest_line(manifest: &mut Option<BufWriter<File>>, definition: &ResourceDefinition) -> Result<()> {
+  if let Some(manifest) = manifest {
+    let mut line = serde_json::to_vec(definition)?;
+    line.push(b'\n');
+    manifest.write_all(&line).await?;
+  }
+
+  Ok(())
+}
+
+/// Prints a table of the discovered resources to stdout, in name order.
+fn print_resource_list(resources: &[ResourceDefinition]) {
+  let rows = resources
+    .iter()
+    .map(|definition| {
+      let info = definition.resource().get_info().as_ref().unwrap();
+      let namespaces = info
+        .namespaces
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>();
+      [
+        info.name.clone(),
+        definition.kind_name().to_owned(),
+        format!("{:x}", info.id),
+        if namespaces.is_empty() {
+          "-".to_owned()
+        } else {
+          namespaces.join(",")
+        },
+      ]
+    })
+    .collect::<Vec<_>>();
+
+  let header = ["NAME", "KIND", "ID", "NAMESPACES"].map(str::to_owned);
+  let mut widths = header.each_ref().map(|column| column.len());
+  for row in &rows {
+    for (width, column) in widths.iter_mut().zip(row) {
+      *width = (*width).max(column.len());
+    }
+  }
+  for row in iter::once(&header).chain(&rows) {
+    println!(
+      "{:<name$}  {:<kind$}  {:<id$}  {}",
+      row[0],
+      row[1],
+      row[2],
+      row[3],
+      name = widths[0],
+      kind = widths[1],
+      id = widths[2]
+    );
+  }
+}
+
+/// Runs the validations of the resources and checks their dependencies and the given id collisions,
+/// reporting problems as diagnostics instead of stopping at the first one.
+async fn check(
+  resources: &mut [ResourceDefinition],
+  proplibs: &[ResourceDefinition],
+  collisions: Vec<(i64, Vec<String>)>,
+) {
+  for definition in resources.iter_mut() {
+    if let ResourceDefinition::Map(resource) = definition {
+      let name = resource.get_info().as_ref().unwrap().name.clone();
+      if let Err(error) = resource.init_proplibs(proplibs).await {
+        diagnostics::error(format!("map {}: {:#}", name, error));
+        continue;
+      }
+      resource.validate_spawn_overlaps();
+    }
+
+    match definition.resource().validate().await {
+      Ok(errors) => {
+        for error in errors {
+          diagnostics::error(error.to_string());
+        }
+      }
+      Err(error) => diagnostics::error(format!("{:#}", error)),
+    }
+  }
+
+  if let Err(error) = generation_order(resources) {
+    diagnostics::error(format!("{:#}", error));
+  }
+
+  for (id, names) in collisions {
+    let message = format!("resource id {} is used by {}", id, names.join(", "));
+    if cli::args().allow_id_collisions {
+      diagnostics::warning(message);
+    } else {
+      diagnostics::error(message);
+    }
+  }
+}
+
+/// Lists the `library/group/prop` of every proplib prop that is not placed on any map, sorted.
+/// Maps that were not initialized this run are parsed again, without touching the resources.
+async fn find_unused_props(resources: &[ResourceDefinition], proplibs: &[ResourceDefinition]) -> Result<Vec<String>> {
+  let mut referenced = HashSet::new();
+  for definition in resources {
+    let ResourceDefinition::Map(resource) = definition else {
+      continue;
+    };
+    let mut maps = resource.get_parsed().map(|(_, map)| map.clone()).collect::<Vec<_>>();
+    if maps.is_empty() {
+      for file in resource.get_map_files() {
+        maps.push(read_map(&file).await?);
+      }
+    }
+    for prop in maps.into_iter().flat_map(|map| map.static_geometry.props) {
+      referenced.insert((prop.library_name, prop.group_name, prop.name));
+    }
+  }
+
+  let mut unused = BTreeSet::new();
+  for definition in proplibs {
+    let ResourceDefinition::Proplib(resource) = definition else {
+      continue;
+    };
+    let Some(library) = &resource.library else {
+      continue;
+    };
+    for group in &library.prop_groups {
+      for prop in &group.props {
+        let key = (library.name.clone(), group.name.clone(), prop.name.clone());
+        if !referenced.contains(&key) {
+          unused.insert(format!("{}/{}/{}", library.name, group.name, prop.name));
+        }
+      }
+    }
+  }
+
+  Ok(unused.into_iter().collect())
+}
+
+/// Decodes the images of every resource, collecting the ones that fail to decode.
+async fn validate_images(resources: &[ResourceDefinition], errors: &mut ValidationErrors) -> Result<()> {
+  for definition in resources {
+    let name = &definition.resource().get_info().as_ref().unwrap().name;
+    for (file, _) in get_images(definition) {
+      // Missing files are reported by the scan
+      let Ok(data) = files::read(&file).await else {
+        continue;
+      };
+      if let Err(error) = imaging::verify(&data) {
+        errors.push(ValidationError {
+          resource: name.clone(),
+          prop: None,
+          message: format!("image {} can't be decoded: {:#}", file.display(), error),
+          file: Some(file),
+        })?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Warns about images whose color type doesn't match what the engine expects for their role.
+async fn validate_color_depth(resources: &[ResourceDefinition]) {
+  for definition in resources {
+    let name = &definition.resource().get_info().as_ref().unwrap().name;
+    for (file, role) in get_images(definition) {
+      // Missing files are reported by the scan
+      let Ok(data) = files::read(&file).await else {
+        continue;
+      };
+      if let Err(error) = imaging::check_color_type(&data, role) {
+        diagnostics::warning(format!("resource {}: {}: {:#}", name, file.display(), error));
+      }
+    }
+  }
+}
+
+/// Lists the image files of a resource together with what they are used for.
+fn get_images(definition: &ResourceDefinition) -> Vec<(PathBuf, ImageRole)> {
+  match definition {
+    ResourceDefinition::Texture(resource) => match resource.get_mask() {
+      Some(mask) => vec![(mask, ImageRole::Alpha)],
+      None => {
+        let mut images = vec![(resource.get_diffuse(), ImageRole::Diffuse)];
+        images.extend(resource.get_alpha().map(|alpha| (alpha, ImageRole::Alpha)));
+        images
+      }
+    },
+    ResourceDefinition::Image(resource) => vec![(resource.get_image(), ImageRole::Diffuse)],
+    ResourceDefinition::MultiframeTexture(resource) => {
+      let mut images = vec![(resource.get_diffuse(), ImageRole::Diffuse)];
+      let alpha = resource.get_alpha();
+      if alpha.exists() {
+        images.push((alpha, ImageRole::Alpha));
+      }
+      images
+    }
+    ResourceDefinition::Object3D(resource) => {
+      let root = resource.get_root();
+      let mut images = Vec::new();
+      // Invalid globs are reported by the scan
+      for image in resource.get_images().unwrap_or_default().into_values() {
+        match image {
+          Object3DImage::Simple(diffuse) => images.push((root.join(diffuse), ImageRole::Diffuse)),
+          Object3DImage::Complex { diffuse, alpha } => {
+            images.push((root.join(diffuse), ImageRole::Diffuse));
+            images.push((root.join(alpha), ImageRole::Alpha));
+          }
+          Object3DImage::Glob { .. } => unreachable!("globs are expanded"),
+        }
+      }
+      images
+    }
+    ResourceDefinition::Proplib(resource) => {
+      let root = resource.get_root();
+      let mut images = Vec::new();
+      for image in resource.images.iter().flat_map(|images| &images.images) {
+        let diffuse = root.join(&image.diffuse);
+        images.push((
+          file_exists_case_insensitive(&diffuse).unwrap_or(diffuse),
+          ImageRole::Diffuse,
+        ));
+        if let Some(alpha) = &image.alpha {
+          let alpha = root.join(alpha);
+          images.push((file_exists_case_insensitive(&alpha).unwrap_or(alpha), ImageRole::Alpha));
+        }
+      }
+      images
+    }
+    _ => Vec::new(),
+  }
+}
+
+/// Groups resource names and their source paths by id, keeping only ids shared by more than one resource.
+fn find_id_collisions(resources: &[ResourceDefinition], sources: &HashMap<String, PathBuf>) -> Vec<(i64, Vec<String>)> {
+  let mut ids = BTreeMap::<i64, Vec<String>>::new();
+  for definition in resources {
+    let info = definition.resource().get_info().as_ref().unwrap();
+    let name = match sources.get(&info.name) {
+      Some(source) => format!("{} ({})", info.name, source.display()),
+      None => info.name.clone(),
+    };
+    ids.entry(info.id).or_default().push(name);
+  }
+
+  ids.into_iter().filter(|(_, names)| names.len() > 1).collect()
+}
+
+/// Lowercase file names of each directory looked up by [`file_exists_case_insensitive`], mapped to their paths.
+/// Inputs don't change during a run, so listings are never invalidated.
+static DIRECTORY_INDEX: Mutex<BTreeMap<PathBuf, Arc<HashMap<String, PathBuf>>>> = Mutex::new(BTreeMap::new());
+
+fn directory_index(directory: &Path) -> Arc<HashMap<String, PathBuf>> {
+  if let Some(index) = DIRECTORY_INDEX.lock().unwrap().get(directory) {
+    return index.clone();
+  }
+
+  let mut index = HashMap::new();
+  for entry in WalkDir::new(directory).max_depth(1).into_iter().flatten() {
+    if entry.file_type().is_file() {
+      let name = entry.file_name().to_str().unwrap().to_lowercase();
+      // Keep the first match, like a linear scan of the directory would
+      index.entry(name).or_insert_with(|| entry.into_path());
+    }
+  }
+
+  let index = Arc::new(index);
+  DIRECTORY_INDEX
+    .lock()
+    .unwrap()
+    .insert(directory.to_path_buf(), index.clone());
+  index
+}
+
+fn file_exists_case_insensitive<P: AsRef<Path>>(filename: P) -> Option<PathBuf> {
+  let filename_str = filename.as_ref().file_name().unwrap().to_str().unwrap().to_lowercase();
+  let parent_dir = filename.as_ref().parent().unwrap_or_else(|| Path::new("."));
+
+  directory_index(parent_dir).get(&filename_str).cloned()
+}
+
+/// Warns if `reference` only names the file `found` by [file_exists_case_insensitive] when ignoring case,
+/// which breaks on case-sensitive hosting unless `--normalize-case` is used.
+fn check_reference_case(context: &str, reference: &Path, found: &Path) {
+  if !cli::args().normalize_case && reference.file_name() != found.file_name() {
+    diagnostics::warning(format!(
+      "{}: {} differs in case from the file {}",
+      context,
+      reference.display(),
+      found.display()
+    ));
+  }
+}
+
+/// Maximum edit distance between a missing and an existing file name to suggest the latter.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Deserializes XML read from `file`, reporting the path of the failing field and the file on error.
+pub fn parse_xml<T: DeserializeOwned>(content: &str, file: &Path) -> Result<T> {
+  let deserializer = &mut quick_xml::de::Deserializer::from_str(content);
+  serde_path_to_error::deserialize(deserializer).with_context(|| format!("failed to parse {}", file.display()))
+}
+
+/// Deserializes YAML read from `file`, reporting the path of the failing key and the file on error.
+pub fn parse_yaml<T: DeserializeOwned>(content: &str, file: &Path) -> Result<T> {
+  let deserializer = serde_yaml::Deserializer::from_str(content);
+  serde_path_to_error::deserialize(deserializer).with_context(|| format!("failed to parse {}", file.display()))
+}
+
+/// Reads the change detection state of the input files written by [write_mtimes].
+async fn read_mtimes(mtimes_file: &Path) -> (HashMap<String, u128>, BTreeMap<String, u32>) {
+  let mut mtimes = HashMap::new();
+  let mut hashes = BTreeMap::new();
+  // A corrupt cache only costs regenerating the resources it can't tell about
+  let content = fs::read_to_string(mtimes_file).await.unwrap_or_else(|error| {
+    diagnostics::warning(format!(
+      "failed to read mtimes file {}, ignoring it: {}",
+      mtimes_file.display(),
+      error
+    ));
+    String::new()
+  });
+  for (index, entry) in content.split('\n').enumerate() {
+    let entry = entry.trim();
+    // `<file>: <mtime>`, followed by ` <hash>` with `--cache-mode hash`.
+    // Files without a hash, e.g. written by mtime mode, are hashed and treated as changed
+    if let Some((file, value)) = entry.split_once(": ") {
+      let (time, hash) = value.split_once(' ').unwrap_or((value, ""));
+      let Ok(time) = time.parse::<u128>() else {
+        diagnostics::warning(format!(
+          "skipping malformed line {} of {}: {:?}",
+          index + 1,
+          mtimes_file.display(),
+          entry
+        ));
+        continue;
+      };
+
+      debug!("{}: {}", file, time);
+      mtimes.insert(file.to_owned(), time);
+      if let Ok(hash) = u32::from_str_radix(hash, 16) {
+        hashes.insert(file.to_owned(), hash);
+      }
+    }
+  }
+
+  (mtimes, hashes)
+}
+
+/// Removes the input files of a resource from the cache, so it is validated and generated again on the next run.
+fn forget_inputs(files: &[String], mtimes: &mut HashMap<String, u128>, hashes: &mut BTreeMap<String, u32>) {
+  for file in files {
+    mtimes.remove(file);
+    hashes.remove(file);
+  }
+}
+
+/// Writes the change detection state of the input files, replacing the cache atomically.
+async fn write_mtimes(
+  mtimes_file: &Path,
+  mtimes: HashMap<String, u128>,
+  hashes: &BTreeMap<String, u32>,
+  cache_mode: CacheMode,
+) -> Result<()> {
+  if let Some(parent) = mtimes_file.parent() {
+    fs::create_dir_all(parent).await?;
+  }
+  // Written next to the cache and renamed over it, so an interrupted run keeps the previous cache
+  let mut file_name = mtimes_file.file_name().unwrap().to_owned();
+  file_name.push(".tmp");
+  let temporary_file = mtimes_file.with_file_name(file_name);
+  let mut file = BufWriter::new(
+    File::create(&temporary_file)
+      .await
+      .with_context(|| format!("failed to create {}", temporary_file.display()))?,
+  );
+  for (input, mtime) in mtimes {
+    let line = match hashes.get(&input) {
+      Some(hash) if cache_mode == CacheMode::Hash => format!("{}: {} {:08x}\n", input, mtime, hash),
+      _ => format!("{}: {}\n", input, mtime),
+    };
+    file.write_all(line.as_bytes()).await?;
+  }
+  file.flush().await?;
+  file.get_ref().sync_all().await?;
+  fs::rename(&temporary_file, mtimes_file)
+    .await
+    .with_context(|| format!("failed to replace {}", mtimes_file.display()))?;
+  Ok(())
+}
+
+/// Creates the output directory of a resource, replacing one left behind by an interrupted run.
+async fn create_output_dir(path: &Path, info: &ResourceInfo) -> Result<()> {
+  fs::create_dir_all(path.parent().unwrap()).await?;
+  match fs::create_dir(path).await {
+    Ok(()) => {}
+    Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+      diagnostics::warning(format!(
+        "directory {} for {:?} already exists, cache is probably corrupt; replacing it",
+        path.display(),
+        info
+      ));
+      // Files the previous generation wrote but this one doesn't must not survive
+      fs::remove_dir_all(path)
+        .await
+        .with_context(|| format!("failed to remove {}", path.display()))?;
+      fs::create_dir(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    }
+    Err(error) => return Err(error).with_context(|| format!("failed to create {}", path.display())),
+  }
+  Ok(())
+}
+
+/// Writes the JSON Schema of `resource.yaml`, see [kind::definition_schema].
+async fn emit_schema(file: &Path) -> Result<()> {
+  let schema = serde_json::to_vec_pretty(&kind::definition_schema())?;
+  fs::write(file, schema)
+    .await
+    .with_context(|| format!("failed to write schema {}", file.display()))?;
+  info!("wrote schema to {}", file.display());
+  Ok(())
+}
+
+/// Deserializes a `resource.yaml`, listing the valid types if `type` is missing or unknown.
+fn parse_definition(content: &str, file: &Path) -> Result<ResourceDefinition> {
+  let value: serde_yaml::Value = parse_yaml(content, file)?;
+  let kinds = ResourceDefinition::KIND_NAMES.join(", ");
+  match value.get("type").map(serde_yaml::Value::as_str) {
+    Some(Some(kind)) if ResourceDefinition::KIND_NAMES.contains(&kind) => {}
+    Some(Some(kind)) => bail!(
+      "failed to parse {}: unknown type `{}`, expected one of {}",
+      file.display(),
+      kind,
+      kinds
+    ),
+    Some(None) => bail!(
+      "failed to parse {}: `type` must be a string, expected one of {}",
+      file.display(),
+      kinds
+    ),
+    None => bail!(
+      "failed to parse {}: missing `type`, expected one of {}",
+      file.display(),
+      kinds
+    ),
+  }
+
+  parse_yaml(content, file)
+}
+
+/// Formats a "file not exists" message, listing similarly named files in the same
+/// directory (same stem with another extension, or a likely typo).
+pub fn missing_file_message<P: AsRef<Path>>(filename: P) -> String {
+  let filename = filename.as_ref();
+  let similar = similar_files(filename);
+  if similar.is_empty() {
+    format!("{} not exists", filename.display())
+  } else {
+    format!(
+      "{} not exists; did you mean {}?",
+      filename.display(),
+      similar.join(", ")
+    )
+  }
+}
+
+fn similar_files(filename: &Path) -> Vec<String> {
+  let Some(name) = filename.file_name().and_then(|name| name.to_str()) else {
+    return Vec::new();
+  };
+  let name = name.to_lowercase();
+  let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&name);
+  let parent_dir = filename.parent().unwrap_or_else(|| Path::new("."));
+
+  let mut similar = Vec::new();
+  for entry in WalkDir::new(parent_dir).max_depth(1).into_iter().flatten() {
+    if !entry.file_type().is_file() {
+      continue;
+    }
+
+    let Some(candidate) = entry.file_name().to_str() else {
+      continue;
+    };
+    let lowercase = candidate.to_lowercase();
+    let candidate_stem = lowercase.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(&lowercase);
+    if candidate_stem == stem || edit_distance(&lowercase, &name) <= MAX_SUGGESTION_DISTANCE {
+      similar.push(candidate.to_owned());
+    }
+  }
+  similar.sort();
+
+  similar
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b = b.chars().collect::<Vec<_>>();
+  let mut previous = (0..=b.len()).collect::<Vec<_>>();
+  for (i, a) in a.chars().enumerate() {
+    let mut current = vec![i + 1];
+    for (j, b) in b.iter().enumerate() {
+      let substitution = previous[j] + usize::from(a != *b);
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+
+  previous[b.len()]
+}
+
+/// Parses the main chunk of a 3DS model, failing instead of panicking on a corrupt file.
+fn parse_3ds(data: &[u8], file: &Path) -> Result<Main> {
+  let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+    let mut data = Cursor::new(data);
+    Parser3DS::new(&mut data).read_main()
+  }));
+  match parsed {
+    Ok(mut main) if !main.is_empty() => Ok(main.swap_remove(0)),
+    _ => bail!("failed to parse 3DS model {}", file.display()),
+  }
+}
+
+fn get_texture_map_name(main: &Main) -> Option<String> {
+  get_texture_map_names(main).into_iter().next()
+}
+
+/// Returns the texture map names of all materials of a 3DS model, in file order.
+#[allow(irrefutable_let_patterns)]
+fn get_texture_map_names(main: &Main) -> Vec<String> {
+  let mut names = Vec::new();
+  if let Main::Editor(editors) = main {
+    for editor in editors {
+      if let Editor::Material(materials) = editor {
+        for material in materials {
+          if let Material::TextureMap(texture_maps) = material {
+            for texture_map in texture_maps {
+              if let MaterialTextureMap::Name(name) = texture_map {
+                names.push(name.clone());
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+  names
+}
+
+/// Fails if a resource under a namespaced directory misses one of the `required` keys of `--require-namespace`.
+fn check_required_namespaces(name: &str, namespaces: &HashMap<String, String>, required: &[String]) -> Result<()> {
+  // Resources outside of namespaced directories are shared by all namespaces
+  if namespaces.is_empty() {
+    return Ok(());
+  }
+  let missing = required
+    .iter()
+    .filter(|key| !namespaces.contains_key(*key))
+    .collect::<Vec<_>>();
+  if !missing.is_empty() {
+    bail!(
+      "resource {}: missing required namespaces {:?}, has {:?}",
+      name,
+      missing,
+      namespaces
+    );
+  }
+  Ok(())
+}
+
+/// Collects the namespaces of `@key=value` components of `path`, from the root down,
+/// so nested directories override the keys of their parents.
+async fn get_namespaces(path: &Path) -> HashMap<String, String> {
+  let mut namespaces = HashMap::new();
+
+  for component in path.components() {
+    if let Some(comp_str) = component.as_os_str().to_str() {
+      // Check if the component matches the pattern @key=value
+      if let Some(namespace) = comp_str.strip_prefix('@') {
+        let parts: Vec<&str> = namespace.split('=').collect();
+        if parts.len() == 2 {
+          let key = parts[0].to_string();
+          let value = parts[1].to_string();
+          if let Some(parent) = namespaces.insert(key.clone(), value.clone()) {
+            trace!("{}: namespace {}={} overrides {}", path.display(), key, value, parent);
+          }
+        }
+      }
+    }
+  }
+
+  namespaces
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn create_output_dir_removes_files_of_previous_generation() {
+    let out = tempfile::tempdir().unwrap();
+    let path = out.path().join("1/0/0/1/1");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(path.join("old.bin"), b"old").unwrap();
+    std::fs::write(path.join("kept.bin"), b"old").unwrap();
+
+    create_output_dir(&path, &ResourceInfo::for_test("textures.stale", 1, 1))
+      .await
+      .unwrap();
+    std::fs::write(path.join("kept.bin"), b"new").unwrap();
+
+    let mut files = std::fs::read_dir(&path)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+      .collect::<Vec<_>>();
+    files.sort();
+    assert_eq!(files, ["kept.bin"]);
+    assert_eq!(std::fs::read(path.join("kept.bin")).unwrap(), b"new");
+  }
+
+  #[tokio::test]
+  async fn create_output_dir_creates_missing_parents() {
+    let out = tempfile::tempdir().unwrap();
+    let path = out.path().join("1/0/0/2/1");
+
+    create_output_dir(&path, &ResourceInfo::for_test("textures.new", 2, 1))
+      .await
+      .unwrap();
+
+    assert!(path.is_dir());
+  }
+
+  fn raw_data(root: &Path) -> RawDataResource {
+    RawDataResource {
+      root: root.to_path_buf(),
+      info: None,
+      files: Vec::new(),
+    }
+  }
+
+  #[tokio::test]
+  async fn compute_version_is_stable_and_in_digest_order() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    let a = root.path().join("a.bin");
+    let b = root.path().join("b.bin");
+    std::fs::write(&a, b"first").unwrap();
+    std::fs::write(&b, b"second").unwrap();
+    let resource = raw_data(root.path());
+
+    let version = compute_version(&resource, vec![a.as_path(), b.as_path()])
+      .await
+      .unwrap();
+    assert_eq!(
+      compute_version(&resource, vec![a.as_path(), b.as_path()])
+        .await
+        .unwrap(),
+      version
+    );
+
+    let mut digest = VersionDigest::new();
+    digest.update_file(b"first");
+    digest.update_file(b"second");
+    assert_eq!(version, digest.finalize());
+    assert_ne!(
+      compute_version(&resource, vec![b.as_path(), a.as_path()])
+        .await
+        .unwrap(),
+      version
+    );
+  }
+
+  #[tokio::test]
+  async fn compute_version_streams_large_files() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    let small = root.path().join("small.bin");
+    let large = root.path().join("large.bin");
+    let large_data = vec![7u8; VERSION_STREAM_THRESHOLD as usize + 1];
+    std::fs::write(&small, b"small").unwrap();
+    std::fs::write(&large, &large_data).unwrap();
+
+    let version = compute_version(&raw_data(root.path()), vec![small.as_path(), large.as_path()])
+      .await
+      .unwrap();
+
+    // Streaming must digest the same bytes as reading the file at once
+    let mut digest = VersionDigest::new();
+    digest.update_file(b"small");
+    digest.update_file(&large_data);
+    assert_eq!(version, digest.finalize());
+  }
+
+  #[tokio::test]
+  async fn compute_version_fails_on_missing_files() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    let missing = root.path().join("missing.bin");
+
+    let error = compute_version(&raw_data(root.path()), vec![missing.as_path()])
+      .await
+      .unwrap_err();
+
+    assert!(format!("{:#}", error).contains("failed to read"), "{:#}", error);
+  }
+
+  #[tokio::test]
+  async fn read_mtimes_recovers_from_truncated_cache() {
+    let out = tempfile::tempdir().unwrap();
+    let file = out.path().join("mtimes");
+    // Interrupted in the middle of the last line
+    std::fs::write(&file, "a.xml: 10 0000abcd\nb.xml: 20\nc.xml: 3x").unwrap();
+
+    let (mtimes, hashes) = read_mtimes(&file).await;
+
+    assert_eq!(
+      mtimes,
+      HashMap::from([("a.xml".to_owned(), 10), ("b.xml".to_owned(), 20)])
+    );
+    assert_eq!(hashes, BTreeMap::from([("a.xml".to_owned(), 0xabcd)]));
+  }
+
+  #[tokio::test]
+  async fn read_mtimes_ignores_unreadable_cache() {
+    let out = tempfile::tempdir().unwrap();
+    let file = out.path().join("mtimes");
+    std::fs::write(&file, [0xff, 0xfe, b':']).unwrap();
+
+    let (mtimes, hashes) = read_mtimes(&file).await;
+
+    assert!(mtimes.is_empty());
+    assert!(hashes.is_empty());
+  }
+
+  #[tokio::test]
+  async fn write_mtimes_replaces_cache() {
+    let out = tempfile::tempdir().unwrap();
+    let file = out.path().join("mtimes");
+    std::fs::write(&file, "old.xml: 1\n").unwrap();
+    let mtimes = HashMap::from([("a.xml".to_owned(), 10), ("b.xml".to_owned(), 20)]);
+    let hashes = BTreeMap::from([("a.xml".to_owned(), 0xabcd)]);
+
+    write_mtimes(&file, mtimes.clone(), &hashes, CacheMode::Hash)
+      .await
+      .unwrap();
+
+    assert_eq!(read_mtimes(&file).await, (mtimes, hashes));
+    assert!(!out.path().join("mtimes.tmp").exists());
+  }
+
+  #[tokio::test]
+  async fn failed_resource_is_left_out_of_the_cache() {
+    let out = tempfile::tempdir().unwrap();
+    let file = out.path().join("mtimes");
+    let mut mtimes = HashMap::from([("good.xml".to_owned(), 1), ("bad.xml".to_owned(), 2)]);
+    let mut hashes = BTreeMap::from([("good.xml".to_owned(), 3), ("bad.xml".to_owned(), 4)]);
+
+    forget_inputs(&["bad.xml".to_owned()], &mut mtimes, &mut hashes);
+    write_mtimes(&file, mtimes, &hashes, CacheMode::Hash).await.unwrap();
+
+    // Unknown files are treated as changed, so the resource is generated again
+    let (mtimes, hashes) = read_mtimes(&file).await;
+    assert_eq!(mtimes, HashMap::from([("good.xml".to_owned(), 1)]));
+    assert_eq!(hashes, BTreeMap::from([("good.xml".to_owned(), 3)]));
+    assert!(!out.path().join("mtimes.tmp").exists());
+  }
+
+  #[tokio::test]
+  async fn localization_in_name_does_not_force_regeneration() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join("sounds/localization_notification");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(path.join(RESOURCE_DEFINITION_FILE), "type: Sound\n").unwrap();
+    std::fs::write(path.join("sound.mp3"), b"ID3").unwrap();
+    let candidate = Candidate::Full(path);
+
+    let first = scan_resource(&candidate, root.path(), &HashMap::new(), None)
+      .await
+      .unwrap();
+    assert!(first.changed);
+
+    // Only localization resources are regenerated with --force-localization, never by name
+    let second = scan_resource(&candidate, root.path(), &first.mtimes, None)
+      .await
+      .unwrap();
+    assert!(matches!(second.definition, ResourceDefinition::Sound(_)));
+    assert!(!second.changed, "{:?}", second.reasons);
+  }
+
+  #[tokio::test]
+  async fn nested_namespace_overrides_parent() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    let path = root.path().join("@gen=1/@theme=a/@theme=b/sounds/click");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(path.join(RESOURCE_DEFINITION_FILE), "type: Sound\n").unwrap();
+    std::fs::write(path.join("sound.mp3"), b"ID3").unwrap();
+
+    let scanned = scan_resource(&Candidate::Full(path), root.path(), &HashMap::new(), None)
+      .await
+      .unwrap();
+
+    let info = scanned.definition.resource().get_info().as_ref().unwrap();
+    assert_eq!(info.name, "sounds.click");
+    assert_eq!(
+      info.namespaces,
+      HashMap::from([("gen".to_owned(), "1".to_owned()), ("theme".to_owned(), "b".to_owned())])
+    );
+  }
+
+  #[test]
+  fn missing_required_namespace_is_reported() {
+    let namespaces = HashMap::from([("gen".to_owned(), "1".to_owned())]);
+    let required = ["gen".to_owned(), "theme".to_owned()];
+
+    let error = check_required_namespaces("proplibs.land", &namespaces, &required).unwrap_err();
+    assert!(
+      error.to_string().contains("missing required namespaces [\"theme\"]"),
+      "{}",
+      error
+    );
+
+    // Shared resources outside of namespaced directories have no keys to miss
+    check_required_namespaces("textures.shared", &HashMap::new(), &required).unwrap();
+  }
+
+  #[tokio::test]
+  async fn read_exclude_patterns_reads_resourceignore() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(
+      root.path().join(RESOURCE_IGNORE_FILE),
+      "# work in progress\n\nmaps.wip*\n  textures.old  \n",
+    )
+    .unwrap();
+
+    let patterns = read_exclude_patterns(root.path()).await.unwrap();
+
+    let patterns = patterns.iter().map(Pattern::as_str).collect::<Vec<_>>();
+    assert_eq!(patterns, ["maps.wip*", "textures.old"]);
+  }
+
+  #[tokio::test]
+  async fn read_exclude_patterns_reports_invalid_line() {
+    cli::init_defaults();
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(root.path().join(RESOURCE_IGNORE_FILE), "maps.*\nmaps.[\n").unwrap();
+
+    let error = read_exclude_patterns(root.path()).await.unwrap_err();
+
+    assert!(
+      format!("{:#}", error).contains(":2: invalid pattern maps.["),
+      "{:#}",
+      error
+    );
+  }
+
+  #[test]
+  fn exclude_takes_precedence_over_only() {
+    let root = Path::new("/resources");
+    let candidates = vec![
+      Candidate::Full(root.join("maps/sandbox")),
+      Candidate::Full(root.join("maps/wip")),
+      Candidate::Short(root.join("textures/grass@Texture.png")),
+    ];
+    let exclude = [Pattern::new("maps.wip").unwrap()];
+
+    let (included, excluded) = exclude_candidates(candidates, root, &exclude).unwrap();
+    assert_eq!(excluded, ["maps.wip"]);
+
+    // Excluded resources are never scanned, so `--only` can't select them
+    let resources = included
+      .iter()
+      .enumerate()
+      .map(|(index, candidate)| {
+        let mut resource = raw_data(root);
+        resource.info = Some(ResourceInfo::for_test(&candidate.name(root).unwrap(), index as i64, 1));
+        ResourceDefinition::RawData(resource)
+      })
+      .collect::<Vec<_>>();
+    let selected = select_matching_resources(&["maps.*".to_owned()], &resources).unwrap();
+    assert_eq!(selected, HashSet::from(["maps.sandbox".to_owned()]));
+  }
+
+  #[test]
+  fn namespace_selects_matching_resources_and_dependencies() {
+    let root = Path::new("/resources");
+    let resource = |name: &str, id: i64, namespaces: &[(&str, &str)], depends_on: &[&str]| {
+      let mut info = ResourceInfo::for_test(name, id, 1);
+      info.namespaces = namespaces
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+      info.properties.depends_on = depends_on.iter().map(|name| name.to_string()).collect();
+      let mut resource = raw_data(root);
+      resource.info = Some(info);
+      ResourceDefinition::RawData(resource)
+    };
+    let resources = vec![
+      resource("maps.summer", 1, &[("gen", "2"), ("theme", "summer")], &[
+        "textures.shared",
+      ]),
+      resource("maps.winter", 2, &[("gen", "2"), ("theme", "winter")], &[]),
+      resource("maps.old", 3, &[("gen", "1"), ("theme", "summer")], &[]),
+      resource("textures.shared", 4, &[], &[]),
+    ];
+
+    let namespaces = [
+      ("gen".to_owned(), "2".to_owned()),
+      ("theme".to_owned(), "summer".to_owned()),
+    ];
+    let selected = select_namespace_resources(&namespaces, &resources);
+    assert_eq!(
+      selected,
+      HashSet::from(["maps.summer".to_owned(), "textures.shared".to_owned()])
+    );
+  }
 }
@@ -0,0 +1,79 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use tracing_subscriber::fmt::MakeWriter;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static BAR: OnceLock<ProgressBar> = OnceLock::new();
+
+/// Allows [start] to show the progress bar, if stdout is a terminal.
+pub fn enable() {
+  ENABLED.store(io::stdout().is_terminal(), Ordering::Relaxed);
+}
+
+/// Shows the progress bar over `total` resources. Has no effect if called again.
+pub fn start(total: usize) {
+  if !ENABLED.load(Ordering::Relaxed) {
+    return;
+  }
+  let style = ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}").expect("valid progress template");
+  let _ = BAR.set(ProgressBar::new(total as u64).with_style(style));
+}
+
+/// Advances the progress bar to the resource `name`.
+pub fn advance(name: &str) {
+  if let Some(bar) = BAR.get() {
+    bar.inc(1);
+    bar.set_message(name.to_owned());
+  }
+}
+
+pub fn finish() {
+  if let Some(bar) = BAR.get() {
+    bar.finish_and_clear();
+  }
+}
+
+/// Writes log events to stdout, hiding the progress bar while they are written so it is redrawn below them.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    match BAR.get() {
+      Some(bar) => bar.suspend(|| io::stdout().write(buf)),
+      None => io::stdout().write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    io::stdout().flush()
+  }
+}
+
+impl<'a> MakeWriter<'a> for LogWriter {
+  type Writer = LogWriter;
+
+  fn make_writer(&'a self) -> Self::Writer {
+    LogWriter
+  }
+}
@@ -0,0 +1,120 @@
+/*
+ * Narukami TO - a server software reimplementation for a certain browser tank game.
+ * Copyright (c) 2025  Daniil Pryima
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Affero General Public License for more details.
+ *
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Version digest construction. By default the version is CRC-32/ISO-HDLC over the contents of
+//! all input files in sorted path order, with nothing between files. `--version-order`,
+//! `--version-framing` and `--version-seed` change how bytes are fed to the CRC, to reproduce
+//! versions computed by other pipelines.
+
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use crc::{Algorithm, Crc, Digest, CRC_32_ISO_HDLC};
+
+use crate::cli;
+
+/// Order input files are fed to the digest in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum VersionOrder {
+  /// Sorted by path, `resource.yaml` included at its sorted position.
+  Path,
+  /// In the order the resource kind lists them, followed by `resource.yaml`.
+  Input,
+}
+
+/// Lengths fed to the digest in addition to file contents, as 4-byte little-endian integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum VersionFraming {
+  None,
+  /// Length of each file after its contents.
+  File,
+  /// Total length of all files after the last file.
+  Total,
+}
+
+static VERSION_CRC: OnceLock<Crc<u32>> = OnceLock::new();
+
+fn version_crc() -> &'static Crc<u32> {
+  VERSION_CRC.get_or_init(|| match cli::args().version_seed {
+    Some(init) => {
+      let algorithm: &'static Algorithm<u32> = Box::leak(Box::new(Algorithm {
+        init,
+        ..CRC_32_ISO_HDLC
+      }));
+      Crc::<u32>::new(algorithm)
+    }
+    None => Crc::<u32>::new(&CRC_32_ISO_HDLC),
+  })
+}
+
+pub struct VersionDigest {
+  digest: Digest<'static, u32>,
+  framing: VersionFraming,
+  total: u32,
+}
+
+impl VersionDigest {
+  pub fn new() -> Self {
+    Self {
+      digest: version_crc().digest(),
+      framing: cli::args().version_framing,
+      total: 0,
+    }
+  }
+
+  pub fn update_file(&mut self, data: &[u8]) {
+    self.update(data);
+    self.end_file(data.len() as u64);
+  }
+
+  /// Feeds part of a file, which must be followed by [`VersionDigest::end_file`] once all of it was fed.
+  pub fn update(&mut self, data: &[u8]) {
+    self.digest.update(data);
+  }
+
+  pub fn end_file(&mut self, length: u64) {
+    // Lengths are framed as u32, as pipelines this reproduces did
+    let length = length as u32;
+    self.total = self.total.wrapping_add(length);
+    if self.framing == VersionFraming::File {
+      self.digest.update(&length.to_le_bytes());
+    }
+  }
+
+  pub fn finalize(mut self) -> u32 {
+    if self.framing == VersionFraming::Total {
+      self.digest.update(&self.total.to_le_bytes());
+    }
+    self.digest.finalize()
+  }
+}
+
+impl Default for VersionDigest {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Parses a seed as decimal or `0x`-prefixed hex.
+pub fn parse_seed(value: &str) -> Result<u32, String> {
+  match value.strip_prefix("0x") {
+    Some(hex) => u32::from_str_radix(hex, 16),
+    None => value.parse(),
+  }
+  .map_err(|error| error.to_string())
+}